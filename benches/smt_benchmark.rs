@@ -1,3 +1,5 @@
+#![allow(clippy::upper_case_acronyms)]
+
 #[macro_use]
 extern crate criterion;
 
@@ -54,6 +56,15 @@ fn bench(c: &mut Criterion) {
         &[5_000, 10_000],
     );
 
+    c.bench_function("SMT get of a 2 leaves tree", |b| {
+        let mut rng = thread_rng();
+        let (smt, keys) = random_smt(2, &mut rng);
+        b.iter(|| {
+            smt.get(&keys[0]).unwrap();
+            smt.get(&random_h256(&mut rng)).unwrap();
+        });
+    });
+
     c.bench_function("SMT generate merkle proof", |b| {
         let mut rng = thread_rng();
         let (smt, mut keys) = random_smt(10_000, &mut rng);
@@ -64,6 +75,51 @@ fn bench(c: &mut Criterion) {
         });
     });
 
+    c.bench_function_over_inputs(
+        "SMT generate multi keys merkle proof",
+        |b, &&size| {
+            let mut rng = thread_rng();
+            let (smt, keys) = random_smt(10_000, &mut rng);
+            let keys: Vec<_> = keys.into_iter().take(size).collect();
+            b.iter(|| {
+                smt.merkle_proof(keys.clone()).unwrap();
+            });
+        },
+        &[1, 8, 64, 512],
+    );
+
+    c.bench_function_over_inputs(
+        "SMT generate per key merkle proofs",
+        |b, &&size| {
+            let mut rng = thread_rng();
+            let (smt, keys) = random_smt(10_000, &mut rng);
+            let keys: Vec<_> = keys.into_iter().take(size).collect();
+            b.iter(|| {
+                for key in &keys {
+                    smt.merkle_proof(vec![*key]).unwrap();
+                }
+            });
+        },
+        &[1, 8, 64, 512],
+    );
+
+    c.bench_function_over_inputs(
+        "SMT generate merkle proof of absent keys",
+        |b, &&absence| {
+            let mut rng = thread_rng();
+            let (smt, _keys) = random_smt(10_000, &mut rng);
+            let keys: Vec<_> = (0..100).map(|_| random_h256(&mut rng)).collect();
+            b.iter(|| {
+                if absence {
+                    smt.absence_proof(keys.clone()).unwrap();
+                } else {
+                    smt.merkle_proof(keys.clone()).unwrap();
+                }
+            });
+        },
+        &[true, false],
+    );
+
     c.bench_function("SMT verify merkle proof", |b| {
         let mut rng = thread_rng();
         let (smt, mut keys) = random_smt(10_000, &mut rng);
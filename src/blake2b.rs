@@ -18,6 +18,7 @@ impl Default for Blake2bHasher {
 }
 
 impl Hasher for Blake2bHasher {
+    const ID: u8 = 1;
     fn write_h256(&mut self, h: &H256) {
         self.0.update(h.as_slice());
     }
@@ -0,0 +1,71 @@
+use crate::{
+    error::Error,
+    traits::Store,
+    tree::{BranchNode, LeafNode},
+    H256,
+};
+use bumpalo::Bump;
+use hashbrown::{DefaultHashBuilder, HashMap};
+
+pub type Map<'bump, K, V> = HashMap<K, V, DefaultHashBuilder, &'bump Bump>;
+
+/// A store allocates nodes in a bumpalo arena
+///
+/// the memory of a batch build is freed at once when the arena is dropped or reset,
+/// the roots are identical to the DefaultStore
+/// the store borrows a Bump which is not Sync, so it is neither Send nor Sync
+#[derive(Debug, Clone)]
+pub struct BumpStore<'bump, V> {
+    branches_map: Map<'bump, H256, BranchNode>,
+    leaves_map: Map<'bump, H256, LeafNode<V>>,
+}
+
+impl<'bump, V> BumpStore<'bump, V> {
+    pub fn new_in(bump: &'bump Bump) -> Self {
+        BumpStore {
+            branches_map: HashMap::new_in(bump),
+            leaves_map: HashMap::new_in(bump),
+        }
+    }
+    pub fn branches_map(&self) -> &Map<'bump, H256, BranchNode> {
+        &self.branches_map
+    }
+    pub fn leaves_map(&self) -> &Map<'bump, H256, LeafNode<V>> {
+        &self.leaves_map
+    }
+    pub fn clear(&mut self) {
+        self.branches_map.clear();
+        self.leaves_map.clear();
+    }
+}
+
+impl<'bump, V: Clone> Store<V> for BumpStore<'bump, V> {
+    fn get_branch(&self, node: &H256) -> Result<Option<BranchNode>, Error> {
+        Ok(self.branches_map.get(node).cloned())
+    }
+    fn get_leaf(&self, leaf_hash: &H256) -> Result<Option<LeafNode<V>>, Error> {
+        Ok(self.leaves_map.get(leaf_hash).cloned())
+    }
+    fn insert_branch(&mut self, node: H256, branch: BranchNode) -> Result<(), Error> {
+        self.branches_map.insert(node, branch);
+        Ok(())
+    }
+    fn insert_leaf(&mut self, leaf_hash: H256, leaf: LeafNode<V>) -> Result<(), Error> {
+        self.leaves_map.insert(leaf_hash, leaf);
+        Ok(())
+    }
+    fn remove_branch(&mut self, node: &H256) -> Result<(), Error> {
+        self.branches_map.remove(node);
+        Ok(())
+    }
+    fn remove_leaf(&mut self, leaf_hash: &H256) -> Result<(), Error> {
+        self.leaves_map.remove(leaf_hash);
+        Ok(())
+    }
+    fn leaf_ref(&self, leaf_hash: &H256) -> Result<Option<&LeafNode<V>>, Error> {
+        Ok(self.leaves_map.get(leaf_hash))
+    }
+    fn take_leaf(&mut self, leaf_hash: &H256) -> Result<Option<LeafNode<V>>, Error> {
+        Ok(self.leaves_map.remove(leaf_hash))
+    }
+}
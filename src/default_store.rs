@@ -27,10 +27,10 @@ impl<V> DefaultStore<V> {
 
 impl<V: Clone> Store<V> for DefaultStore<V> {
     fn get_branch(&self, node: &H256) -> Result<Option<BranchNode>, Error> {
-        Ok(self.branches_map.get(node).map(Clone::clone))
+        Ok(self.branches_map.get(node).cloned())
     }
     fn get_leaf(&self, leaf_hash: &H256) -> Result<Option<LeafNode<V>>, Error> {
-        Ok(self.leaves_map.get(leaf_hash).map(Clone::clone))
+        Ok(self.leaves_map.get(leaf_hash).cloned())
     }
     fn insert_branch(&mut self, node: H256, branch: BranchNode) -> Result<(), Error> {
         self.branches_map.insert(node, branch);
@@ -48,6 +48,12 @@ impl<V: Clone> Store<V> for DefaultStore<V> {
         self.leaves_map.remove(leaf_hash);
         Ok(())
     }
+    fn leaf_ref(&self, leaf_hash: &H256) -> Result<Option<&LeafNode<V>>, Error> {
+        Ok(self.leaves_map.get(leaf_hash))
+    }
+    fn take_leaf(&mut self, leaf_hash: &H256) -> Result<Option<LeafNode<V>>, Error> {
+        Ok(self.leaves_map.remove(leaf_hash))
+    }
 }
 
 cfg_if::cfg_if! {
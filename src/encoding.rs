@@ -0,0 +1,96 @@
+//! Text encodings of bytes, hex and standard base64 with padding
+
+use crate::{
+    error::{Error, Result},
+    h256::hex_value,
+    string::String,
+    vec::Vec,
+};
+
+const HEX_CHARS: &[u8; 16] = b"0123456789abcdef";
+const BASE64_CHARS: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Encode bytes as lowercase hex
+pub fn hex_encode(bytes: &[u8]) -> String {
+    let mut s = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        s.push(HEX_CHARS[(byte >> 4) as usize] as char);
+        s.push(HEX_CHARS[(byte & 0xf) as usize] as char);
+    }
+    s
+}
+
+/// Decode hex, an optional `0x` prefix is allowed
+/// return InvalidHexChar error for a non hex character, OddHexLength error for odd length
+pub fn hex_decode(s: &str) -> Result<Vec<u8>> {
+    let s = s.strip_prefix("0x").unwrap_or(s);
+    if let Some(c) = s.chars().find(|c| !c.is_ascii_hexdigit()) {
+        return Err(Error::InvalidHexChar(c));
+    }
+    if !s.len().is_multiple_of(2) {
+        return Err(Error::OddHexLength(s.len()));
+    }
+    Ok(s.as_bytes()
+        .chunks(2)
+        .map(|pair| (hex_value(pair[0]) << 4) | hex_value(pair[1]))
+        .collect())
+}
+
+/// Encode bytes as standard base64 with padding
+pub fn base64_encode(bytes: &[u8]) -> String {
+    let mut s = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let mut buf = [0u8; 3];
+        buf[..chunk.len()].copy_from_slice(chunk);
+        let n = (buf[0] as u32) << 16 | (buf[1] as u32) << 8 | buf[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                s.push(BASE64_CHARS[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                s.push('=');
+            }
+        }
+    }
+    s
+}
+
+/// Decode standard base64 with padding
+/// return InvalidBase64 error if the length, a character, or the padding is invalid,
+/// the unused bits of the last character must be zero
+pub fn base64_decode(s: &str) -> Result<Vec<u8>> {
+    let s = s.as_bytes();
+    if !s.len().is_multiple_of(4) {
+        return Err(Error::InvalidBase64);
+    }
+    let mut bytes = Vec::with_capacity(s.len() / 4 * 3);
+    for (i, chunk) in s.chunks(4).enumerate() {
+        let is_last = i == s.len() / 4 - 1;
+        let padding = chunk.iter().rev().take_while(|c| **c == b'=').count();
+        if padding > 2 || (padding > 0 && !is_last) {
+            return Err(Error::InvalidBase64);
+        }
+        let mut n = 0u32;
+        for c in &chunk[..4 - padding] {
+            n = n << 6 | base64_value(*c).ok_or(Error::InvalidBase64)? as u32;
+        }
+        n <<= 6 * padding as u32;
+        let decoded = [(n >> 16) as u8, (n >> 8) as u8, n as u8];
+        let len = 3 - padding;
+        if decoded[len..].iter().any(|byte| *byte != 0) {
+            return Err(Error::InvalidBase64);
+        }
+        bytes.extend_from_slice(&decoded[..len]);
+    }
+    Ok(bytes)
+}
+
+fn base64_value(c: u8) -> Option<u8> {
+    match c {
+        b'A'..=b'Z' => Some(c - b'A'),
+        b'a'..=b'z' => Some(c - b'a' + 26),
+        b'0'..=b'9' => Some(c - b'0' + 52),
+        b'+' => Some(62),
+        b'/' => Some(63),
+        _ => None,
+    }
+}
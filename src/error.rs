@@ -1,4 +1,6 @@
-use crate::{string, H256};
+#[cfg(feature = "alloc")]
+use crate::string;
+use crate::H256;
 
 pub type Result<T> = ::core::result::Result<T, Error>;
 
@@ -9,12 +11,45 @@ pub enum Error {
     CorruptedProof,
     EmptyProof,
     EmptyKeys,
-    IncorrectNumberOfLeaves { expected: usize, actual: usize },
+    IncorrectNumberOfLeaves {
+        expected: usize,
+        actual: usize,
+    },
+    #[cfg(feature = "alloc")]
     Store(string::String),
     CorruptedStack,
     NonSiblings,
     InvalidCode(u8),
     NonMergableRange,
+    NonExistentKey(H256),
+    DuplicateKey(H256),
+    UnsortedKeys,
+    InvalidHexLength(usize),
+    InvalidHexChar(char),
+    VerificationFailed,
+    TruncatedProof {
+        expected: usize,
+        actual: usize,
+    },
+    HasherMismatch {
+        expected: u8,
+        actual: u8,
+    },
+    UnsupportedProofVersion(u8),
+    InvalidValue(H256),
+    InvalidDeltaLength(usize),
+    TooManyKeys {
+        max: usize,
+        actual: usize,
+    },
+    CorruptedLeaf {
+        key: H256,
+    },
+    ExistentKey(H256),
+    OddHexLength(usize),
+    InvalidBase64,
+    PendingTombstones,
+    RefUnsupported,
 }
 
 impl core::fmt::Display for Error {
@@ -42,6 +77,7 @@ impl core::fmt::Display for Error {
                     expected, actual
                 )?;
             }
+            #[cfg(feature = "alloc")]
             Error::Store(err_msg) => {
                 write!(f, "Backend store error: {}", err_msg)?;
             }
@@ -57,6 +93,72 @@ impl core::fmt::Display for Error {
             Error::NonMergableRange => {
                 write!(f, "Ranges can not be merged")?;
             }
+            Error::NonExistentKey(key) => {
+                write!(f, "Key {:?} does not exist", key)?;
+            }
+            Error::DuplicateKey(key) => {
+                write!(f, "Duplicate key {:?}", key)?;
+            }
+            Error::UnsortedKeys => {
+                write!(f, "Keys are not in ascending order")?;
+            }
+            Error::InvalidHexLength(len) => {
+                write!(f, "Invalid hex length, expected 64 actual {}", len)?;
+            }
+            Error::InvalidHexChar(c) => {
+                write!(f, "Invalid hex character {:?}", c)?;
+            }
+            Error::VerificationFailed => {
+                write!(f, "Merkle proof verification failed")?;
+            }
+            Error::TruncatedProof { expected, actual } => {
+                write!(
+                    f,
+                    "Truncated proof, expected at least {} bytes actual {}",
+                    expected, actual
+                )?;
+            }
+            Error::HasherMismatch { expected, actual } => {
+                write!(
+                    f,
+                    "Proof is serialized for hasher {} instead of {}",
+                    actual, expected
+                )?;
+            }
+            Error::UnsupportedProofVersion(version) => {
+                write!(f, "Unsupported proof version {}", version)?;
+            }
+            Error::InvalidValue(key) => {
+                write!(f, "Invalid value for key {:?}", key)?;
+            }
+            Error::InvalidDeltaLength(len) => {
+                write!(f, "Invalid tree delta length {}", len)?;
+            }
+            Error::TooManyKeys { max, actual } => {
+                write!(
+                    f,
+                    "Too many keys, expected at most {} actual {}",
+                    max, actual
+                )?;
+            }
+            Error::CorruptedLeaf { key } => {
+                write!(f, "Corrupted store, leaf {:?} does not match its hash", key)?;
+            }
+            Error::ExistentKey(key) => {
+                write!(f, "Key {:?} exists", key)?;
+            }
+            Error::OddHexLength(len) => {
+                write!(f, "Invalid hex length, expected even actual {}", len)?;
+            }
+            Error::InvalidBase64 => {
+                write!(f, "Invalid base64")?;
+            }
+            Error::PendingTombstones => {
+                write!(f, "Tombstoned keys are pending, compact the tree first")?;
+            }
+            Error::RefUnsupported => {
+                write!(f, "Store does not lend references of leaves")?;
+            }
         }
         Ok(())
     }
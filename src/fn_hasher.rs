@@ -0,0 +1,36 @@
+use crate::{traits::Hasher, vec::Vec, H256};
+
+/// A hasher calls a user supplied hash function,
+/// the written data is buffered and hashed once in `finish`
+///
+/// Trees and proofs create hashers by `Default`, which is not implemented by closures.
+/// To use `FnHasher` with them, wrap it in a type which implements `Default` by `FnHasher::new`.
+pub struct FnHasher<F> {
+    hash_fn: F,
+    buf: Vec<u8>,
+}
+
+impl<F: Fn(&[u8]) -> [u8; 32]> FnHasher<F> {
+    /// Create a hasher from a hash function
+    pub fn new(hash_fn: F) -> Self {
+        FnHasher {
+            hash_fn,
+            buf: Vec::with_capacity(64),
+        }
+    }
+}
+
+impl<F: Fn(&[u8]) -> [u8; 32] + Default> Default for FnHasher<F> {
+    fn default() -> Self {
+        FnHasher::new(F::default())
+    }
+}
+
+impl<F: Fn(&[u8]) -> [u8; 32]> Hasher for FnHasher<F> {
+    fn write_h256(&mut self, h: &H256) {
+        self.buf.extend_from_slice(h.as_slice());
+    }
+    fn finish(self) -> H256 {
+        (self.hash_fn)(&self.buf).into()
+    }
+}
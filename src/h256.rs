@@ -1,3 +1,4 @@
+use crate::error::{Error, Result};
 use core::cmp::Ordering;
 
 /// Represent 256 bits
@@ -28,7 +29,7 @@ impl H256 {
     pub fn set_bit(&mut self, i: u8) {
         let byte_pos = i / BYTE_SIZE;
         let bit_pos = i % BYTE_SIZE;
-        self.0[byte_pos as usize] |= 1 << bit_pos as u8;
+        self.0[byte_pos as usize] |= 1 << bit_pos;
     }
 
     #[inline]
@@ -38,16 +39,80 @@ impl H256 {
         self.0[byte_pos as usize] &= !((1 << bit_pos) as u8);
     }
 
+    /// Number of set bits in the 256 bits
+    pub fn count_ones(&self) -> u32 {
+        self.0.iter().map(|byte| byte.count_ones()).sum()
+    }
+
+    /// Number of unset bits in the 256 bits
+    pub fn count_zeros(&self) -> u32 {
+        256 - self.count_ones()
+    }
+
     pub fn as_slice(&self) -> &[u8] {
         &self.0[..]
     }
 
+    /// Parse H256 from 64 hex characters, an optional `0x` prefix is allowed
+    /// the bytes are in the same order as `as_slice`
+    pub fn from_hex(s: &str) -> Result<Self> {
+        let s = s.strip_prefix("0x").unwrap_or(s);
+        if let Some(c) = s.chars().find(|c| !c.is_ascii_hexdigit()) {
+            return Err(Error::InvalidHexChar(c));
+        }
+        if s.len() != 64 {
+            return Err(Error::InvalidHexLength(s.len()));
+        }
+        let mut h = ZERO;
+        for (byte, pair) in h.0.iter_mut().zip(s.as_bytes().chunks(2)) {
+            *byte = (hex_value(pair[0]) << 4) | hex_value(pair[1]);
+        }
+        Ok(h)
+    }
+
+    /// Mutable bytes of H256, useful for reading data into H256 in place
+    pub fn as_mut_bytes(&mut self) -> &mut [u8; 32] {
+        &mut self.0
+    }
+
+    /// Return the next H256 in key order, or None for the max value
+    /// keys are ordered from heigher bits to lower bits(255..0),
+    /// which treats the bytes as a little endian integer
+    pub fn checked_next(&self) -> Option<Self> {
+        let mut next = *self;
+        for byte in next.0.iter_mut() {
+            if *byte == u8::MAX {
+                *byte = 0;
+            } else {
+                *byte += 1;
+                return Some(next);
+            }
+        }
+        None
+    }
+
+    /// Return the previous H256 in key order, or None for zero
+    pub fn checked_prev(&self) -> Option<Self> {
+        let mut prev = *self;
+        for byte in prev.0.iter_mut() {
+            if *byte == 0 {
+                *byte = u8::MAX;
+            } else {
+                *byte -= 1;
+                return Some(prev);
+            }
+        }
+        None
+    }
+
     /// Treat H256 as a path in a tree
     /// fork height is the number of common bits(from heigher to lower: 255..=0) of two H256
     pub fn fork_height(&self, key: &H256) -> u8 {
-        for h in (0..=core::u8::MAX).rev() {
-            if self.get_bit(h) != key.get_bit(h) {
-                return h;
+        // compare from the most significant byte
+        for i in (0..self.0.len()).rev() {
+            let diff = self.0[i] ^ key.0[i];
+            if diff != 0 {
+                return i as u8 * BYTE_SIZE + (BYTE_SIZE - 1 - diff.leading_zeros() as u8);
             }
         }
         0
@@ -56,14 +121,14 @@ impl H256 {
     /// Treat H256 as a path in a tree
     /// return parent_path of self
     pub fn parent_path(&self, height: u8) -> Self {
-        if height == core::u8::MAX {
+        if height == u8::MAX {
             H256::zero()
         } else {
             self.copy_bits(height + 1)
         }
     }
 
-    /// Copy bits and return a new H256
+    /// Copy bits from start to 255 and return a new H256, bits below start are zero
     pub fn copy_bits(&self, start: u8) -> Self {
         let mut target = H256::zero();
 
@@ -79,6 +144,65 @@ impl H256 {
 
         target
     }
+
+    /// Keep the top n bits (the most significant in tree order) and zero the rest
+    /// same as copy_bits(256 - n), mask_prefix(0) is zero
+    pub fn mask_prefix(&self, n: u8) -> Self {
+        if n == 0 {
+            return H256::zero();
+        }
+        self.copy_bits((256 - n as u16) as u8)
+    }
+
+    /// Reverse all 256 bits, bit i moves to bit 255 - i
+    /// useful for converting keys of implementations which index bits from the other side
+    pub fn reverse_bits(&self) -> Self {
+        let mut reversed = H256::zero();
+        for (dst, byte) in reversed.0.iter_mut().rev().zip(self.0.iter()) {
+            *dst = byte.reverse_bits();
+        }
+        reversed
+    }
+
+    /// Reverse the order of bytes, the bits in each byte are kept
+    pub fn reverse_bytes(&self) -> Self {
+        let mut reversed = self.0;
+        reversed.reverse();
+        H256(reversed)
+    }
+
+    /// Check the top prefix_len bits of self and key are equal,
+    /// that is key is in the subtree of prefix self, always true for prefix_len 0
+    pub fn is_prefix_of(&self, key: &H256, prefix_len: u8) -> bool {
+        self.mask_prefix(prefix_len) == key.mask_prefix(prefix_len)
+    }
+
+    /// Copy bits in range into dst, other bits of dst are cleared
+    /// range is u16 to express the full range `0..256`, an empty range clears dst
+    pub fn copy_bits_into(&self, range: core::ops::Range<u16>, dst: &mut H256) {
+        debug_assert!(range.end <= 256, "range out of bounds");
+        for (i, (dst_byte, byte)) in dst.0.iter_mut().zip(self.0.iter()).enumerate() {
+            let byte_start = i as u16 * BYTE_SIZE as u16;
+            let byte_end = byte_start + BYTE_SIZE as u16;
+            let lo = range.start.clamp(byte_start, byte_end) - byte_start;
+            let hi = range.end.clamp(byte_start, byte_end) - byte_start;
+            let mask = if lo >= hi {
+                0
+            } else {
+                ((1u16 << hi) - (1u16 << lo)) as u8
+            };
+            *dst_byte = byte & mask;
+        }
+    }
+}
+
+// c must be an ascii hex digit
+pub(crate) fn hex_value(c: u8) -> u8 {
+    match c {
+        b'0'..=b'9' => c - b'0',
+        b'a'..=b'f' => c - b'a' + 10,
+        _ => c - b'A' + 10,
+    }
 }
 
 impl PartialOrd for H256 {
@@ -100,8 +224,24 @@ impl From<[u8; 32]> for H256 {
     }
 }
 
-impl Into<[u8; 32]> for H256 {
-    fn into(self: H256) -> [u8; 32] {
-        self.0
+impl From<H256> for [u8; 32] {
+    fn from(val: H256) -> Self {
+        val.0
+    }
+}
+
+impl AsRef<[u8]> for H256 {
+    fn as_ref(&self) -> &[u8] {
+        self.as_slice()
+    }
+}
+
+/// Deref to the bytes, `Borrow<[u8]>` is not implemented
+/// because the order of H256 is different with the order of byte slices
+impl core::ops::Deref for H256 {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.as_slice()
     }
 }
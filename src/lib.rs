@@ -61,21 +61,63 @@
 
 #![cfg_attr(not(feature = "std"), no_std)]
 
+// enter a tracing span until the end of the block, no-op without the tracing feature
+#[allow(unused_macros)] // unused without the alloc feature
+macro_rules! trace_span {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!($($arg)*).entered();
+    };
+}
+
+// emit a tracing event, no-op without the tracing feature
+#[allow(unused_macros)] // unused without the alloc feature
+macro_rules! trace_event {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "tracing")]
+        tracing::debug!($($arg)*);
+    };
+}
+
 #[cfg(feature = "blake2b")]
 pub mod blake2b;
-pub mod default_store;
 pub mod error;
 pub mod h256;
 pub mod merge;
-pub mod merkle_proof;
-#[cfg(test)]
-mod tests;
+pub mod node_codec;
+mod proof_format;
 pub mod traits;
-pub mod tree;
 
 pub use h256::H256;
-pub use merkle_proof::{CompiledMerkleProof, MerkleProof};
-pub use tree::SparseMerkleTree;
+pub use merge::hash_leaf;
+pub use proof_format::{verify_single_no_alloc, PROOF_VERSION, SUPPORTED_VERSIONS};
+
+// the tree and proofs need an allocator, which is enabled by the alloc feature
+cfg_if::cfg_if! {
+    if #[cfg(feature = "alloc")] {
+        #[cfg(feature = "bumpalo")]
+        pub mod bump_store;
+        pub mod default_store;
+        pub mod encoding;
+        pub mod fn_hasher;
+        pub mod merkle_proof;
+        #[cfg(feature = "std")]
+        pub mod proof_cache;
+        pub mod proof_pool;
+        pub mod proof_state;
+        pub mod snapshot;
+        #[cfg(test)]
+        mod tests;
+        pub mod tree;
+
+        pub use merge::branch_node_id;
+        pub use merkle_proof::{
+            AbsenceProof, CircuitProof, CompiledMerkleProof, MerkleProof, PartialMerkleProof,
+            ProofVerifier, VerifySummary,
+        };
+        pub use tree::SparseMerkleTree;
+    }
+}
 
 /// Expected path size: log2(256) * 2, used for hint vector capacity
 pub const EXPECTED_PATH_SIZE: usize = 16;
@@ -85,10 +127,12 @@ cfg_if::cfg_if! {
         use std::collections;
         use std::vec;
         use std::string;
-    } else {
+        use std::sync;
+    } else if #[cfg(feature = "alloc")] {
         extern crate alloc;
         use alloc::collections;
         use alloc::vec;
         use alloc::string;
+        use alloc::sync;
     }
 }
@@ -1,9 +1,13 @@
 use crate::h256::H256;
 use crate::traits::Hasher;
+#[cfg(feature = "alloc")]
+use crate::tree::{BranchNode, NodeType};
 
 /// Merge two hash
 /// this function optimized for ZERO_HASH
 /// if one of lhs or rhs is ZERO_HASH, this function just return another one
+///
+/// the tree hashes branches by merge_branch, this function equals to it for the DefaultCodec
 pub fn merge<H: Hasher + Default>(lhs: &H256, rhs: &H256) -> H256 {
     if lhs.is_zero() {
         return *rhs;
@@ -16,8 +20,30 @@ pub fn merge<H: Hasher + Default>(lhs: &H256, rhs: &H256) -> H256 {
     hasher.finish()
 }
 
+/// Merge two hash of a branch at height, node_key is the parent path of the branch
+/// the branch is fed by Hasher::write_branch, see node_codec
+/// if one of lhs or rhs is ZERO_HASH, this function just return another one
+pub fn merge_branch<H: Hasher + Default>(
+    height: u8,
+    node_key: &H256,
+    lhs: &H256,
+    rhs: &H256,
+) -> H256 {
+    if lhs.is_zero() {
+        return *rhs;
+    } else if rhs.is_zero() {
+        return *lhs;
+    }
+    let mut hasher = H::default();
+    hasher.write_branch(height, node_key, lhs, rhs);
+    hasher.finish()
+}
+
 /// hash_leaf = hash(key | value)
 /// zero value represent delete the key, this function return zero for zero value
+///
+/// there is no domain separation, a leaf and a DefaultCodec branch are both
+/// an untagged 64 bytes preimage, use node_codec::CodecHasher to tag branches if it is required
 pub fn hash_leaf<H: Hasher + Default>(key: &H256, value: &H256) -> H256 {
     if value.is_zero() {
         return H256::zero();
@@ -27,3 +53,22 @@ pub fn hash_leaf<H: Hasher + Default>(key: &H256, value: &H256) -> H256 {
     hasher.write_h256(value);
     hasher.finish()
 }
+
+/// Derive the store key of a branch, that is the hash of the subtree
+///
+/// merge_branch(fork_height, parent path, left, right) for a pair, the leaf hash for a single
+#[cfg(feature = "alloc")]
+pub fn branch_node_id<H: Hasher + Default>(branch: &BranchNode) -> H256 {
+    match branch.node_type {
+        NodeType::Pair(node, sibling) => {
+            let height = branch.fork_height;
+            let node_key = branch.key.parent_path(height);
+            if branch.key.get_bit(height) {
+                merge_branch::<H>(height, &node_key, &sibling, &node)
+            } else {
+                merge_branch::<H>(height, &node_key, &node, &sibling)
+            }
+        }
+        NodeType::Single(leaf_hash) => leaf_hash,
+    }
+}
@@ -1,14 +1,37 @@
 use crate::{
     collections::{BTreeMap, VecDeque},
+    encoding,
     error::{Error, Result},
-    merge::{hash_leaf, merge},
+    merge::{hash_leaf, merge_branch},
+    proof_format::{check_hasher_id, check_version, ProofReader, PROOF_VERSION},
+    string::String,
     traits::Hasher,
+    vec,
     vec::Vec,
     H256,
 };
 
 type Range = core::ops::Range<usize>;
 
+/// Result of MerkleProof::verify_with_summary
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VerifySummary {
+    /// the computed root matches
+    pub matched: bool,
+    /// number of leaves claimed present (non-zero value)
+    pub present: usize,
+    /// number of leaves claimed absent (zero value)
+    pub absent: usize,
+}
+
+/// Merkle proof of multiple leaves
+///
+/// leaves_path: for each leaf in ascending order of keys, the heights where its subtree merges with a non-zero sibling
+/// proof: the non-zero siblings (node, height) which can't be computed from the leaves,
+/// emitted from lower heights to higher heights, and in ascending order of keys (left before right) at a same height
+///
+/// the leaves are not in the proof, the verifier must know the values of all keys,
+/// see PartialMerkleProof for verifying a subset of keys
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct MerkleProof {
     leaves_path: Vec<Vec<u8>>,
@@ -44,6 +67,102 @@ impl MerkleProof {
         &self.proof
     }
 
+    /// Serialize merkle proof into bytes, in format of PROOF_VERSION
+    ///
+    /// layout, integers are in little endian:
+    /// version: u8, see SUPPORTED_VERSIONS
+    /// hasher id: u8, see Hasher::ID
+    /// leaves count: u32, then for each leaf: path length: u16 | heights: [u8]
+    /// proof count: u32, then for each proof: node: [u8; 32] | height: u8
+    pub fn serialize<H: Hasher>(&self) -> Vec<u8> {
+        let size = 10
+            + self
+                .leaves_path
+                .iter()
+                .map(|path| 2 + path.len())
+                .sum::<usize>()
+            + self.proof.len() * 33;
+        let mut buf = Vec::with_capacity(size);
+        buf.push(PROOF_VERSION);
+        buf.push(H::ID);
+        buf.extend_from_slice(&(self.leaves_path.len() as u32).to_le_bytes());
+        for path in &self.leaves_path {
+            buf.extend_from_slice(&(path.len() as u16).to_le_bytes());
+            buf.extend_from_slice(path);
+        }
+        buf.extend_from_slice(&(self.proof.len() as u32).to_le_bytes());
+        for (node, height) in &self.proof {
+            buf.extend_from_slice(node.as_slice());
+            buf.push(*height);
+        }
+        buf
+    }
+
+    /// Deserialize merkle proof from bytes, see serialize for the layout
+    /// return UnsupportedProofVersion error if the version is unknown,
+    /// return HasherMismatch error if the proof is serialized for another hasher
+    pub fn deserialize<H: Hasher>(bytes: &[u8]) -> Result<Self> {
+        let mut verifier = ProofVerifier::new();
+        verifier.feed(bytes)?;
+        verifier.into_proof::<H>()
+    }
+
+    /// Serialize merkle proof into hex, see serialize
+    pub fn to_hex<H: Hasher>(&self) -> String {
+        encoding::hex_encode(&self.serialize::<H>())
+    }
+
+    /// Deserialize merkle proof from hex, see deserialize and encoding::hex_decode
+    pub fn from_hex<H: Hasher>(s: &str) -> Result<Self> {
+        Self::deserialize::<H>(&encoding::hex_decode(s)?)
+    }
+
+    /// Serialize merkle proof into base64, see serialize
+    pub fn to_base64<H: Hasher>(&self) -> String {
+        encoding::base64_encode(&self.serialize::<H>())
+    }
+
+    /// Deserialize merkle proof from base64, see deserialize and encoding::base64_decode
+    pub fn from_base64<H: Hasher>(s: &str) -> Result<Self> {
+        Self::deserialize::<H>(&encoding::base64_decode(s)?)
+    }
+
+    // deserialize bytes into self by ProofVerifier, the outer buffers are reused,
+    // self is cleared on error, see deserialize
+    pub(crate) fn deserialize_into<H: Hasher>(&mut self, bytes: &[u8]) -> Result<()> {
+        let mut verifier = ProofVerifier::with_buffers(
+            core::mem::take(&mut self.leaves_path),
+            core::mem::take(&mut self.proof),
+        );
+        let result = verifier
+            .feed(bytes)
+            .and_then(|()| verifier.check_complete::<H>());
+        self.leaves_path = verifier.leaves_path;
+        self.proof = verifier.proof;
+        if result.is_err() {
+            self.clear();
+        }
+        result
+    }
+
+    // clear the proof, the allocated buffers are kept
+    pub(crate) fn clear(&mut self) {
+        self.leaves_path.clear();
+        self.proof.clear();
+    }
+
+    /// Verify merkle proof and return the CompiledMerkleProof for later verification
+    /// the proof is verified by the compiled form, so it is only parsed once
+    pub fn verify_and_compile<H: Hasher + Default>(
+        self,
+        root: &H256,
+        leaves: Vec<(H256, H256)>,
+    ) -> Result<(bool, CompiledMerkleProof)> {
+        let compiled_proof = self.compile(leaves.clone())?;
+        let valid = compiled_proof.verify::<H>(root, leaves)?;
+        Ok((valid, compiled_proof))
+    }
+
     /// convert merkle proof into CompiledMerkleProof
     pub fn compile(self, mut leaves: Vec<(H256, H256)>) -> Result<CompiledMerkleProof> {
         if leaves.is_empty() {
@@ -99,7 +218,6 @@ impl MerkleProof {
                         continue;
                     }
                     let (proof, proof_height) = proof.pop_front().ok_or(Error::CorruptedProof)?;
-                    let proof_height = proof_height;
                     if height < proof_height {
                         height = proof_height;
                     }
@@ -109,7 +227,7 @@ impl MerkleProof {
                     (parent_key, parent_program, height)
                 };
 
-            if height == core::u8::MAX {
+            if height == u8::MAX {
                 if proof.is_empty() {
                     return Ok(CompiledMerkleProof(parent_program.0));
                 } else {
@@ -128,84 +246,191 @@ impl MerkleProof {
     ///
     /// return EmptyProof error when proof is empty
     /// return CorruptedProof error when proof is invalid
-    pub fn compute_root<H: Hasher + Default>(self, mut leaves: Vec<(H256, H256)>) -> Result<H256> {
-        if leaves.is_empty() {
-            return Err(Error::EmptyKeys);
-        } else if leaves.len() != self.leaves_count() {
-            return Err(Error::IncorrectNumberOfLeaves {
-                expected: self.leaves_count(),
-                actual: leaves.len(),
-            });
-        }
+    pub fn compute_root<H: Hasher + Default>(self, leaves: Vec<(H256, H256)>) -> Result<H256> {
+        self.compute_root_borrowed::<H>(&leaves)
+    }
 
-        let (leaves_path, proof) = self.take();
-        let mut leaves_path: Vec<VecDeque<_>> = leaves_path.into_iter().map(Into::into).collect();
-        let mut proof: VecDeque<_> = proof.into();
+    /// Compute root from proof over borrowed leaves
+    /// see compute_root
+    pub fn compute_root_borrowed<'a, H: Hasher + Default>(
+        self,
+        leaves: impl IntoIterator<Item = &'a (H256, H256)>,
+    ) -> Result<H256> {
+        self.compute_root_ref::<H>(leaves)
+    }
 
-        // sort leaves
-        leaves.sort_unstable_by_key(|(k, _v)| *k);
-        // tree_buf: (height, key) -> (key_index, node)
-        let mut tree_buf: BTreeMap<_, _> = leaves
-            .into_iter()
-            .enumerate()
-            .map(|(i, (k, v))| ((0, k), (i, hash_leaf::<H>(&k, &v))))
-            .collect();
-        // rebuild the tree from bottom to top
-        while !tree_buf.is_empty() {
-            // pop_front from tree_buf, the API is unstable
-            let (&(mut height, key), &(leaf_index, node)) = tree_buf.iter().next().unwrap();
-            tree_buf.remove(&(height, key));
+    // compute root without consuming the proof
+    pub(crate) fn compute_root_ref<'a, H: Hasher + Default>(
+        &self,
+        leaves: impl IntoIterator<Item = &'a (H256, H256)>,
+    ) -> Result<H256> {
+        let tree_buf = leaves_tree_buf(leaves, self.leaves_count(), hash_leaf::<H>)?;
+        let mut leaves_path: Vec<&[u8]> = self.leaves_path.iter().map(|path| &path[..]).collect();
+        compute_root_from_paths(
+            tree_buf,
+            &mut leaves_path,
+            self.proof.iter().copied(),
+            merge_branch::<H>,
+        )
+    }
 
-            if proof.is_empty() && tree_buf.is_empty() {
-                return Ok(node);
+    /// Verify merkle proof, hashes are computed by the hash function instead of a Hasher
+    /// useful for routing hashes to an accelerator
+    ///
+    /// hash receives same data as a Hasher: key | value for leaves, lhs | rhs for branches,
+    /// zero leaves and merging with zero are skipped like merge::hash_leaf and merge::merge_branch
+    pub fn verify_with_hash_fn(
+        &self,
+        root: &H256,
+        leaves: Vec<(H256, H256)>,
+        mut hash: impl FnMut(&[u8]) -> H256,
+    ) -> Result<bool> {
+        if root.is_zero() {
+            return verify_zero_root(&leaves);
+        }
+        let mut buf = [0u8; 64];
+        let tree_buf = leaves_tree_buf(&leaves, self.leaves_count(), |key, value| {
+            if value.is_zero() {
+                return H256::zero();
             }
+            buf[..32].copy_from_slice(key.as_slice());
+            buf[32..].copy_from_slice(value.as_slice());
+            hash(&buf)
+        })?;
+        let mut leaves_path: Vec<&[u8]> = self.leaves_path.iter().map(|path| &path[..]).collect();
+        let calculated_root = compute_root_from_paths(
+            tree_buf,
+            &mut leaves_path,
+            self.proof.iter().copied(),
+            |_height, _node_key, lhs, rhs| {
+                if lhs.is_zero() {
+                    return *rhs;
+                } else if rhs.is_zero() {
+                    return *lhs;
+                }
+                buf[..32].copy_from_slice(lhs.as_slice());
+                buf[32..].copy_from_slice(rhs.as_slice());
+                hash(&buf)
+            },
+        )?;
+        Ok(&calculated_root == root)
+    }
 
-            let mut sibling_key = key.parent_path(height);
-            if !key.get_bit(height) {
-                sibling_key.set_bit(height)
-            }
-            let (sibling, sibling_height) =
-                if Some(&(height, sibling_key)) == tree_buf.keys().next() {
-                    let (_leaf_index, sibling) = tree_buf
-                        .remove(&(height, sibling_key))
-                        .expect("pop sibling");
-                    (sibling, height)
-                } else {
-                    let merge_height = leaves_path[leaf_index].front().copied().unwrap_or(height);
-                    if height != merge_height {
-                        let parent_key = key.copy_bits(merge_height);
-                        // skip zeros
-                        tree_buf.insert((merge_height, parent_key), (leaf_index, node));
-                        continue;
+    /// Extract the proof of a single key from a multi-leaves proof
+    /// all_leaves are the (key, value) pairs of this proof, the merged siblings of key are collected
+    /// while computing root, return NonExistentKey error if key is not in all_leaves
+    pub fn extract_single<H: Hasher + Default>(
+        &self,
+        key: H256,
+        all_leaves: &[(H256, H256)],
+    ) -> Result<MerkleProof> {
+        if !all_leaves.iter().any(|(k, _v)| k == &key) {
+            return Err(Error::NonExistentKey(key));
+        }
+        let tree_buf = leaves_tree_buf(all_leaves, self.leaves_count(), hash_leaf::<H>)?;
+        let mut leaves_path: Vec<&[u8]> = self.leaves_path.iter().map(|path| &path[..]).collect();
+        let mut path = Vec::new();
+        let mut proof = Vec::new();
+        compute_root_from_paths(
+            tree_buf,
+            &mut leaves_path,
+            self.proof.iter().copied(),
+            |height, node_key, lhs, rhs| {
+                // the non-zero subtree at the other side of key is the sibling of key
+                if &key.parent_path(height) == node_key {
+                    let sibling = if key.get_bit(height) { lhs } else { rhs };
+                    if !sibling.is_zero() {
+                        path.push(height);
+                        proof.push((*sibling, height));
                     }
-                    let (node, height) = proof.pop_front().ok_or(Error::CorruptedProof)?;
-                    (node, height)
-                };
-            if height < sibling_height {
-                height = sibling_height;
-            }
-            // skip zero merkle path
-            let parent_key = key.parent_path(height);
+                }
+                merge_branch::<H>(height, node_key, lhs, rhs)
+            },
+        )?;
+        if path.is_empty() {
+            // the tree only contains one leaf
+            path.push(u8::MAX);
+        }
+        Ok(MerkleProof::new(vec![path], proof))
+    }
 
-            let parent = if key.get_bit(height) {
-                merge::<H>(&sibling, &node)
-            } else {
-                merge::<H>(&node, &sibling)
-            };
+    /// Trace the hashes derived while computing root, a diagnostic for proofs failing to verify,
+    /// only available in debug builds
+    ///
+    /// return whether the derived root matches root, and the trace starts with the leaf hashes at
+    /// height 0 in order of leaves, followed by (height, parent) of each merge in order of computation,
+    /// the last entry is the derived root, compare the entries with another implementation to find
+    /// the first diverged merge
+    #[cfg(debug_assertions)]
+    pub fn verify_trace<H: Hasher + Default>(
+        &self,
+        root: &H256,
+        leaves: &[(H256, H256)],
+    ) -> Result<(bool, Vec<(u8, H256)>)> {
+        let mut trace = Vec::new();
+        let tree_buf = leaves_tree_buf(leaves, self.leaves_count(), |key, value| {
+            let node = hash_leaf::<H>(key, value);
+            trace.push((0, node));
+            node
+        })?;
+        let mut leaves_path: Vec<&[u8]> = self.leaves_path.iter().map(|path| &path[..]).collect();
+        let derived_root = compute_root_from_paths(
+            tree_buf,
+            &mut leaves_path,
+            self.proof.iter().copied(),
+            |height, node_key, lhs, rhs| {
+                let parent = merge_branch::<H>(height, node_key, lhs, rhs);
+                trace.push((height, parent));
+                parent
+            },
+        )?;
+        debug_assert_eq!(
+            trace.last().map(|(_height, node)| *node),
+            Some(derived_root)
+        );
+        Ok((&derived_root == root, trace))
+    }
 
-            if height == core::u8::MAX {
-                if proof.is_empty() {
-                    return Ok(parent);
-                } else {
-                    return Err(Error::CorruptedProof);
-                }
-            } else {
-                leaves_path[leaf_index].pop_front();
-                tree_buf.insert((height + 1, parent_key), (leaf_index, parent));
-            }
+    /// Verify a serialized merkle proof without building MerkleProof
+    /// the proof nodes are read from the bytes while computing root, see serialize for the layout
+    ///
+    /// return TruncatedProof error if the bytes end unexpectedly
+    pub fn verify_from_bytes<H: Hasher + Default>(
+        proof: &[u8],
+        root: &H256,
+        leaves: Vec<(H256, H256)>,
+    ) -> Result<bool> {
+        let mut reader = ProofReader {
+            bytes: proof,
+            offset: 0,
+        };
+        let version = reader.read(1)?[0];
+        check_version(version)?;
+        check_hasher_id::<H>(reader.read(1)?[0])?;
+        let leaves_count = reader.read_u32()? as usize;
+        let mut leaves_path = Vec::new();
+        for _ in 0..leaves_count {
+            let len = reader.read_u16()? as usize;
+            leaves_path.push(reader.read(len)?);
+        }
+        let proof_count = reader.read_u32()? as usize;
+        let proof_bytes = reader.read(proof_count.saturating_mul(33))?;
+        if reader.offset != proof.len() {
+            return Err(Error::CorruptedProof);
+        }
+        if root.is_zero() {
+            return verify_zero_root(&leaves);
         }
 
-        Err(Error::CorruptedProof)
+        let tree_buf = leaves_tree_buf(&leaves, leaves_count, hash_leaf::<H>)?;
+        let proof = proof_bytes.chunks_exact(33).map(|item| {
+            let mut node = [0u8; 32];
+            node.copy_from_slice(&item[..32]);
+            (node.into(), item[32])
+        });
+        let calculated_root =
+            compute_root_from_paths(tree_buf, &mut leaves_path, proof, merge_branch::<H>)?;
+        Ok(&calculated_root == root)
     }
 
     /// Verify merkle proof
@@ -215,16 +440,432 @@ impl MerkleProof {
         root: &H256,
         leaves: Vec<(H256, H256)>,
     ) -> Result<bool> {
+        self.verify_borrowed::<H>(root, &leaves)
+    }
+
+    /// Verify merkle proof over borrowed leaves
+    /// see compute_root_borrowed
+    ///
+    /// a zero root is the empty tree, only zero values are valid against it,
+    /// the proof is not used in this case
+    pub fn verify_borrowed<'a, H: Hasher + Default>(
+        self,
+        root: &H256,
+        leaves: impl IntoIterator<Item = &'a (H256, H256)>,
+    ) -> Result<bool> {
+        trace_span!(
+            "verify",
+            leaves = self.leaves_count(),
+            proof_size = self.proof.len()
+        );
+        if root.is_zero() {
+            return verify_zero_root(leaves);
+        }
+        Ok(&self.compute_root_borrowed::<H>(leaves)? == root)
+    }
+
+    /// Verify merkle proof, return VerificationFailed error on mismatch
+    pub fn verify_strict<H: Hasher + Default>(
+        &self,
+        root: &H256,
+        leaves: Vec<(H256, H256)>,
+    ) -> Result<()> {
+        if self.clone().verify::<H>(root, leaves)? {
+            Ok(())
+        } else {
+            Err(Error::VerificationFailed)
+        }
+    }
+
+    /// Verify merkle proof, return the calculated root on mismatch
+    /// useful for logging the discrepancy
+    pub fn verify_or_root<H: Hasher + Default>(
+        self,
+        root: &H256,
+        leaves: Vec<(H256, H256)>,
+    ) -> Result<core::result::Result<(), H256>> {
+        if root.is_zero() && verify_zero_root(&leaves)? {
+            return Ok(Ok(()));
+        }
         let calculated_root = self.compute_root::<H>(leaves)?;
-        Ok(&calculated_root == root)
+        if &calculated_root == root {
+            Ok(Ok(()))
+        } else {
+            Ok(Err(calculated_root))
+        }
+    }
+
+    /// Verify merkle proof, and count the present and absent leaves
+    /// the counts are of the claims, they are proven only if matched is true
+    pub fn verify_with_summary<H: Hasher + Default>(
+        &self,
+        root: &H256,
+        leaves: Vec<(H256, H256)>,
+    ) -> Result<VerifySummary> {
+        let present = leaves.iter().filter(|(_k, v)| !v.is_zero()).count();
+        let absent = leaves.len() - present;
+        let matched = self.clone().verify::<H>(root, leaves)?;
+        Ok(VerifySummary {
+            matched,
+            present,
+            absent,
+        })
+    }
+
+    /// Verify merkle proof, and check at least n of leaves are present(non-zero value)
+    /// return false if the proof is invalid
+    pub fn verify_threshold<H: Hasher + Default>(
+        self,
+        root: &H256,
+        leaves: Vec<(H256, H256)>,
+        n: usize,
+    ) -> Result<bool> {
+        let present = leaves.iter().filter(|(_k, v)| !v.is_zero()).count();
+        Ok(self.verify::<H>(root, leaves)? && present >= n)
+    }
+
+    /// Verify merkle proof of absent keys, the proof is generated by SparseMerkleTree::absence_proof
+    /// same as verify with zero values
+    pub fn verify_absence<H: Hasher + Default>(self, root: &H256, keys: Vec<H256>) -> Result<bool> {
+        let leaves = keys.into_iter().map(|k| (k, H256::zero())).collect();
+        self.verify::<H>(root, leaves)
+    }
+
+    /// Verify the old leaves are unchanged from old_root to new_root
+    /// self is the proof of SparseMerkleTree::consistency_proof against new_root,
+    /// old_proof is the proof of the same keys against old_root
+    ///
+    /// the tree only keeps the latest version, so old_proof must be generated by
+    /// SparseMerkleTree::merkle_proof before the tree is updated
+    pub fn verify_consistency<H: Hasher + Default>(
+        self,
+        old_proof: MerkleProof,
+        old_root: &H256,
+        new_root: &H256,
+        old_leaves: Vec<(H256, H256)>,
+    ) -> Result<bool> {
+        Ok(old_proof.verify_borrowed::<H>(old_root, &old_leaves)?
+            && self.verify_borrowed::<H>(new_root, &old_leaves)?)
+    }
+
+    /// Verify merkle proof against a hex encoded root, see H256::from_hex
+    pub fn verify_hex<H: Hasher + Default>(
+        &self,
+        root_hex: &str,
+        leaves: Vec<(H256, H256)>,
+    ) -> Result<bool> {
+        let root = H256::from_hex(root_hex)?;
+        self.clone().verify::<H>(&root, leaves)
+    }
+
+    /// Verify a transition from old_root to new_root
+    /// changes are (key, old value, new value) of the touched keys,
+    /// a merkle proof doesn't depend on leaf values, so the siblings are shared by both roots
+    pub fn verify_transition<H: Hasher + Default>(
+        &self,
+        old_root: &H256,
+        new_root: &H256,
+        changes: Vec<(H256, H256, H256)>,
+    ) -> Result<bool> {
+        let (old_leaves, new_leaves): (Vec<_>, Vec<_>) = changes
+            .into_iter()
+            .map(|(key, old_value, new_value)| ((key, old_value), (key, new_value)))
+            .unzip();
+        Ok(self.clone().verify_borrowed::<H>(old_root, &old_leaves)?
+            && self.clone().verify_borrowed::<H>(new_root, &new_leaves)?)
+    }
+}
+
+// verify leaves against a zero root, which is the empty tree,
+// only zero values are valid against it, every verify front-end calls it before using the proof
+pub(crate) fn verify_zero_root<'a>(
+    leaves: impl IntoIterator<Item = &'a (H256, H256)>,
+) -> Result<bool> {
+    let mut leaves = leaves.into_iter().peekable();
+    if leaves.peek().is_none() {
+        return Err(Error::EmptyKeys);
+    }
+    Ok(leaves.all(|(_key, value)| value.is_zero()))
+}
+
+// tree_buf: (height, key) -> (key_index, node)
+type TreeBuf = BTreeMap<(u8, H256), (usize, H256)>;
+
+// hash leaves into tree_buf by hash_leaf_fn(key, value), leaves are indexed in order of keys
+fn leaves_tree_buf<'a>(
+    leaves: impl IntoIterator<Item = &'a (H256, H256)>,
+    leaves_count: usize,
+    mut hash_leaf_fn: impl FnMut(&H256, &H256) -> H256,
+) -> Result<TreeBuf> {
+    let mut tree_buf: TreeBuf = leaves
+        .into_iter()
+        .map(|(k, v)| ((0, *k), (0, hash_leaf_fn(k, v))))
+        .collect();
+    if tree_buf.is_empty() {
+        return Err(Error::EmptyKeys);
+    } else if tree_buf.len() != leaves_count {
+        return Err(Error::IncorrectNumberOfLeaves {
+            expected: leaves_count,
+            actual: tree_buf.len(),
+        });
+    }
+    for (i, (_key, (leaf_index, _node))) in tree_buf.iter_mut().enumerate() {
+        *leaf_index = i;
+    }
+    Ok(tree_buf)
+}
+
+// compute root from hashed leaves, the merkle path heights of each leaf, and proof nodes
+// branches are merged by merge_fn(height, node_key, lhs, rhs), see merge::merge_branch
+fn compute_root_from_paths(
+    mut tree_buf: TreeBuf,
+    leaves_path: &mut [&[u8]],
+    proof: impl Iterator<Item = (H256, u8)>,
+    mut merge_fn: impl FnMut(u8, &H256, &H256, &H256) -> H256,
+) -> Result<H256> {
+    let mut proof = proof.peekable();
+    // rebuild the tree from bottom to top
+    while !tree_buf.is_empty() {
+        // pop_front from tree_buf, the API is unstable
+        let (&(mut height, key), &(leaf_index, node)) = tree_buf.iter().next().unwrap();
+        tree_buf.remove(&(height, key));
+
+        if proof.peek().is_none() && tree_buf.is_empty() {
+            return Ok(node);
+        }
+
+        let mut sibling_key = key.parent_path(height);
+        if !key.get_bit(height) {
+            sibling_key.set_bit(height)
+        }
+        let (sibling, sibling_height) = if Some(&(height, sibling_key)) == tree_buf.keys().next() {
+            let (_leaf_index, sibling) = tree_buf
+                .remove(&(height, sibling_key))
+                .expect("pop sibling");
+            (sibling, height)
+        } else {
+            let merge_height = leaves_path[leaf_index].first().copied().unwrap_or(height);
+            if height != merge_height {
+                let parent_key = key.copy_bits(merge_height);
+                // skip zeros
+                tree_buf.insert((merge_height, parent_key), (leaf_index, node));
+                continue;
+            }
+            let (node, height) = proof.next().ok_or(Error::CorruptedProof)?;
+            (node, height)
+        };
+        if height < sibling_height {
+            height = sibling_height;
+        }
+        // skip zero merkle path
+        let parent_key = key.parent_path(height);
+
+        let parent = if key.get_bit(height) {
+            merge_fn(height, &parent_key, &sibling, &node)
+        } else {
+            merge_fn(height, &parent_key, &node, &sibling)
+        };
+
+        if height == u8::MAX {
+            if proof.peek().is_none() {
+                return Ok(parent);
+            } else {
+                return Err(Error::CorruptedProof);
+            }
+        } else {
+            let path = &mut leaves_path[leaf_index];
+            if !path.is_empty() {
+                *path = &path[1..];
+            }
+            tree_buf.insert((height + 1, parent_key), (leaf_index, parent));
+        }
+    }
+
+    Err(Error::CorruptedProof)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ParseState {
+    Version,
+    HasherId,
+    LeavesCount,
+    PathLength,
+    // remain heights of current path
+    Path(usize),
+    ProofCount,
+    // remain proof items
+    Proof(usize),
+    Done,
+}
+
+/// Verify a serialized merkle proof while the bytes arrive
+///
+/// the bytes are parsed once they are fed,
+/// only an incomplete field is buffered, see MerkleProof::serialize for the layout
+#[derive(Debug, Clone)]
+pub struct ProofVerifier {
+    state: ParseState,
+    // bytes of an incomplete field
+    buf: Vec<u8>,
+    hasher_id: u8,
+    leaves_count: usize,
+    leaves_path: Vec<Vec<u8>>,
+    proof: Vec<(H256, u8)>,
+}
+
+impl Default for ProofVerifier {
+    fn default() -> Self {
+        ProofVerifier::new()
+    }
+}
+
+impl ProofVerifier {
+    pub fn new() -> Self {
+        Self::with_buffers(Vec::new(), Vec::new())
+    }
+
+    // parse into the cleared buffers
+    fn with_buffers(mut leaves_path: Vec<Vec<u8>>, mut proof: Vec<(H256, u8)>) -> Self {
+        leaves_path.clear();
+        proof.clear();
+        ProofVerifier {
+            state: ParseState::Version,
+            buf: Vec::with_capacity(33),
+            hasher_id: 0,
+            leaves_count: 0,
+            leaves_path,
+            proof,
+        }
+    }
+
+    /// Feed next chunk of the serialized proof
+    /// return CorruptedProof error if bytes are fed after the end of proof,
+    /// return UnsupportedProofVersion error if the version is unknown
+    pub fn feed(&mut self, mut bytes: &[u8]) -> Result<()> {
+        while !bytes.is_empty() {
+            let field_size = match self.state {
+                ParseState::Version | ParseState::HasherId => 1,
+                ParseState::LeavesCount | ParseState::ProofCount => 4,
+                ParseState::PathLength => 2,
+                ParseState::Path(remain) => {
+                    // heights are copied without buffering
+                    let len = core::cmp::min(remain, bytes.len());
+                    self.leaves_path
+                        .last_mut()
+                        .expect("path")
+                        .extend_from_slice(&bytes[..len]);
+                    bytes = &bytes[len..];
+                    if remain == len {
+                        self.next_path();
+                    } else {
+                        self.state = ParseState::Path(remain - len);
+                    }
+                    continue;
+                }
+                ParseState::Proof(_) => 33,
+                ParseState::Done => return Err(Error::CorruptedProof),
+            };
+            let len = core::cmp::min(field_size - self.buf.len(), bytes.len());
+            self.buf.extend_from_slice(&bytes[..len]);
+            bytes = &bytes[len..];
+            if self.buf.len() == field_size {
+                self.parse_field()?;
+                self.buf.clear();
+            }
+        }
+        Ok(())
+    }
+
+    /// Verify the fed proof
+    /// return CorruptedProof error if the proof is incomplete,
+    /// return HasherMismatch error if the proof is serialized for another hasher
+    pub fn finalize<H: Hasher + Default>(
+        self,
+        root: &H256,
+        leaves: Vec<(H256, H256)>,
+    ) -> Result<bool> {
+        self.into_proof::<H>()?.verify::<H>(root, leaves)
+    }
+
+    fn into_proof<H: Hasher>(self) -> Result<MerkleProof> {
+        self.check_complete::<H>()?;
+        Ok(MerkleProof::new(self.leaves_path, self.proof))
+    }
+
+    fn check_complete<H: Hasher>(&self) -> Result<()> {
+        if self.state != ParseState::Done {
+            return Err(Error::CorruptedProof);
+        }
+        check_hasher_id::<H>(self.hasher_id)
+    }
+
+    // parse the buffered field
+    fn parse_field(&mut self) -> Result<()> {
+        match self.state {
+            ParseState::Version => {
+                let version = self.buf[0];
+                check_version(version)?;
+                self.state = ParseState::HasherId;
+            }
+            ParseState::HasherId => {
+                self.hasher_id = self.buf[0];
+                self.state = ParseState::LeavesCount;
+            }
+            ParseState::LeavesCount => {
+                let mut count = [0u8; 4];
+                count.copy_from_slice(&self.buf);
+                self.leaves_count = u32::from_le_bytes(count) as usize;
+                self.next_path();
+            }
+            ParseState::PathLength => {
+                let mut len = [0u8; 2];
+                len.copy_from_slice(&self.buf);
+                let len = u16::from_le_bytes(len) as usize;
+                self.leaves_path.push(Vec::with_capacity(len));
+                if len == 0 {
+                    self.next_path();
+                } else {
+                    self.state = ParseState::Path(len);
+                }
+            }
+            ParseState::ProofCount => {
+                let mut count = [0u8; 4];
+                count.copy_from_slice(&self.buf);
+                let count = u32::from_le_bytes(count) as usize;
+                self.state = if count == 0 {
+                    ParseState::Done
+                } else {
+                    ParseState::Proof(count)
+                };
+            }
+            ParseState::Proof(remain) => {
+                let mut node = [0u8; 32];
+                node.copy_from_slice(&self.buf[..32]);
+                self.proof.push((node.into(), self.buf[32]));
+                self.state = if remain == 1 {
+                    ParseState::Done
+                } else {
+                    ParseState::Proof(remain - 1)
+                };
+            }
+            ParseState::Path(_) | ParseState::Done => unreachable!("no buffered field"),
+        }
+        Ok(())
+    }
+
+    // start parsing next path, or the proof if all paths are parsed
+    fn next_path(&mut self) {
+        self.state = if self.leaves_path.len() < self.leaves_count {
+            ParseState::PathLength
+        } else {
+            ParseState::ProofCount
+        };
     }
 }
 
 fn leaf_program(leaf_index: usize) -> (Vec<u8>, Option<Range>) {
-    let mut program = Vec::with_capacity(1);
-    program.push(0x4C);
     (
-        program,
+        vec![0x4C],
         Some(Range {
             start: leaf_index,
             end: leaf_index + 1,
@@ -286,16 +927,209 @@ fn merge_program(
     Ok((program, Some(range)))
 }
 
+/// Merkle proof carries the leaf hashes of its keys, see `SparseMerkleTree::partial_proof`
+///
+/// a verifier knowing the values of only some keys uses the leaf hashes of the others as opaque nodes,
+/// leaf_hashes are in ascending order of keys, zero for absent keys
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartialMerkleProof {
+    pub proof: MerkleProof,
+    pub leaf_hashes: Vec<H256>,
+}
+
+impl PartialMerkleProof {
+    /// Verify the known leaves are consistent with root, the other keys of all_keys are "don't care"
+    /// all_keys are the keys of the proof, return NonExistentKey error if a known key is not in all_keys
+    pub fn verify_partial<H: Hasher + Default>(
+        &self,
+        root: &H256,
+        known_leaves: Vec<(H256, H256)>,
+        mut all_keys: Vec<H256>,
+    ) -> Result<bool> {
+        all_keys.sort_unstable();
+        all_keys.dedup();
+        if all_keys.len() != self.leaf_hashes.len() {
+            return Err(Error::IncorrectNumberOfLeaves {
+                expected: self.leaf_hashes.len(),
+                actual: all_keys.len(),
+            });
+        }
+        let mut nodes: Vec<(H256, H256)> =
+            all_keys.into_iter().zip(self.leaf_hashes.clone()).collect();
+        for (key, value) in &known_leaves {
+            let i = nodes
+                .binary_search_by_key(key, |(k, _node)| *k)
+                .map_err(|_| Error::NonExistentKey(*key))?;
+            nodes[i].1 = hash_leaf::<H>(key, value);
+        }
+        if root.is_zero() {
+            return Ok(nodes.iter().all(|(_key, node)| node.is_zero()));
+        }
+        let tree_buf = leaves_tree_buf(&nodes, self.proof.leaves_count(), |_key, node| *node)?;
+        let mut leaves_path: Vec<&[u8]> = self
+            .proof
+            .leaves_path
+            .iter()
+            .map(|path| &path[..])
+            .collect();
+        let calculated_root = compute_root_from_paths(
+            tree_buf,
+            &mut leaves_path,
+            self.proof.proof.iter().copied(),
+            merge_branch::<H>,
+        )?;
+        Ok(&calculated_root == root)
+    }
+}
+
+/// Proof of absence of a key with its neighbors, see `SparseMerkleTree::prove_absent`
+///
+/// left and right are the (key, value) of the nearest leaves, None if there is no leaf at that side,
+/// proof is the merkle proof of the neighbors and the absent key
+///
+/// the siblings of the proof are the subtrees without the proven keys, each covers a range of keys
+/// entirely between or outside the neighbors, so the neighbors are adjacent if no non-zero sibling is between them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AbsenceProof {
+    pub key: H256,
+    pub left: Option<(H256, H256)>,
+    pub right: Option<(H256, H256)>,
+    pub proof: MerkleProof,
+}
+
+impl AbsenceProof {
+    /// Verify the key is absent and falls strictly between the present neighbors,
+    /// and the neighbors are adjacent, that is no other leaf is between them
+    pub fn verify_absent<H: Hasher + Default>(&self, root: &H256) -> Result<bool> {
+        let mut leaves = Vec::with_capacity(3);
+        if let Some((left_key, left_value)) = self.left {
+            if left_key >= self.key || left_value.is_zero() {
+                return Ok(false);
+            }
+            leaves.push((left_key, left_value));
+        }
+        leaves.push((self.key, H256::zero()));
+        if let Some((right_key, right_value)) = self.right {
+            if right_key <= self.key || right_value.is_zero() {
+                return Ok(false);
+            }
+            leaves.push((right_key, right_value));
+        }
+        if root.is_zero() {
+            return self.proof.clone().verify::<H>(root, leaves);
+        }
+
+        let lower = self.left.map(|(k, _v)| k);
+        let upper = self.right.map(|(k, _v)| k);
+        let between = |key: &H256| {
+            lower.is_none_or(|lower| &lower < key) && upper.is_none_or(|upper| key < &upper)
+        };
+        let mut adjacent = true;
+        let tree_buf = leaves_tree_buf(&leaves, self.proof.leaves_count(), hash_leaf::<H>)?;
+        let mut leaves_path: Vec<&[u8]> = self
+            .proof
+            .leaves_path
+            .iter()
+            .map(|path| &path[..])
+            .collect();
+        let calculated_root = compute_root_from_paths(
+            tree_buf,
+            &mut leaves_path,
+            self.proof.proof.iter().copied(),
+            |height, node_key, lhs, rhs| {
+                for (is_right, node) in [(false, lhs), (true, rhs)] {
+                    // the first key of the subtree
+                    let mut start = *node_key;
+                    if is_right {
+                        start.set_bit(height);
+                    }
+                    let proven = leaves.iter().any(|(key, _value)| {
+                        &key.parent_path(height) == node_key && key.get_bit(height) == is_right
+                    });
+                    if !proven && !node.is_zero() && between(&start) {
+                        adjacent = false;
+                    }
+                }
+                merge_branch::<H>(height, node_key, lhs, rhs)
+            },
+        )?;
+        Ok(adjacent && &calculated_root == root)
+    }
+}
+
+/// Merkle proof of a single key with a sibling at every height, see `SparseMerkleTree::circuit_friendly_proof`
+///
+/// the verification has a fixed number of steps, it is suitable to be expressed as a circuit:
+///
+/// ```text
+/// node = hash_leaf(key, value)
+/// for height in 0..=255:
+///     parent_path = key.parent_path(height)
+///     if key.get_bit(height):  // direction bit, the node is on the right
+///         node = merge_branch(height, parent_path, siblings[height], node)
+///     else:
+///         node = merge_branch(height, parent_path, node, siblings[height])
+/// root = node
+/// ```
+///
+/// a zero sibling is an empty subtree, merge_branch returns the other side if one side is zero
+/// and hashes the branch otherwise, see merge::merge_branch and merge::hash_leaf
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CircuitProof {
+    pub key: H256,
+    pub siblings: [H256; 256],
+}
+
+impl CircuitProof {
+    /// Direction bits from height 0 to 255, true if the node is on the right of its sibling
+    pub fn directions(&self) -> [bool; 256] {
+        let mut directions = [false; 256];
+        for (height, direction) in directions.iter_mut().enumerate() {
+            *direction = self.key.get_bit(height as u8);
+        }
+        directions
+    }
+
+    /// Compute root by the fold described in CircuitProof
+    pub fn compute_root<H: Hasher + Default>(&self, value: &H256) -> H256 {
+        let mut node = hash_leaf::<H>(&self.key, value);
+        for (height, sibling) in self.siblings.iter().enumerate() {
+            let height = height as u8;
+            let parent_path = self.key.parent_path(height);
+            node = if self.key.get_bit(height) {
+                merge_branch::<H>(height, &parent_path, sibling, &node)
+            } else {
+                merge_branch::<H>(height, &parent_path, &node, sibling)
+            };
+        }
+        node
+    }
+
+    /// Verify the value of key against root
+    pub fn verify<H: Hasher + Default>(&self, root: &H256, value: &H256) -> bool {
+        &self.compute_root::<H>(value) == root
+    }
+}
+
+/// Max size of the stack used by CompiledMerkleProof,
+/// pending nodes of a valid proof have different heights, plus the current one
+const MAX_STACK_SIZE: usize = 257;
+
 /// An structure optimized for verify merkle proof
 #[derive(Debug, Clone)]
 pub struct CompiledMerkleProof(pub Vec<u8>);
 
 impl CompiledMerkleProof {
+    /// Compute root from compiled proof
+    /// leaves: a vector of (key, value)
+    ///
+    /// no heap allocation happens, the leaves are sorted in place
+    /// and the nodes are computed on a fixed size stack
     pub fn compute_root<H: Hasher + Default>(&self, mut leaves: Vec<(H256, H256)>) -> Result<H256> {
         leaves.sort_unstable_by_key(|(k, _v)| *k);
         let mut program_index = 0;
         let mut leave_index = 0;
-        let mut stack = Vec::new();
+        let mut stack = Stack::new();
         while program_index < self.0.len() {
             let code = self.0[program_index];
             program_index += 1;
@@ -306,7 +1140,7 @@ impl CompiledMerkleProof {
                         return Err(Error::CorruptedStack);
                     }
                     let (k, v) = leaves[leave_index];
-                    stack.push((k, hash_leaf::<H>(&k, &v)));
+                    stack.push((k, hash_leaf::<H>(&k, &v)))?;
                     leave_index += 1;
                 }
                 // P
@@ -326,11 +1160,11 @@ impl CompiledMerkleProof {
                     let (key, value) = stack.pop().unwrap();
                     let parent_key = key.parent_path(height);
                     let parent = if key.get_bit(height) {
-                        merge::<H>(&proof, &value)
+                        merge_branch::<H>(height, &parent_key, &proof, &value)
                     } else {
-                        merge::<H>(&value, &proof)
+                        merge_branch::<H>(height, &parent_key, &value, &proof)
                     };
-                    stack.push((parent_key, parent));
+                    stack.push((parent_key, parent))?;
                 }
                 // H
                 0x48 => {
@@ -356,12 +1190,13 @@ impl CompiledMerkleProof {
                     if !(sibling_key_a == parent_key_b && (a_set ^ b_set)) {
                         return Err(Error::NonSiblings);
                     }
+                    let node_key = key_a.parent_path(height);
                     let parent = if key_a.get_bit(height) {
-                        merge::<H>(&value_b, &value_a)
+                        merge_branch::<H>(height, &node_key, &value_b, &value_a)
                     } else {
-                        merge::<H>(&value_a, &value_b)
+                        merge_branch::<H>(height, &node_key, &value_a, &value_b)
                     };
-                    stack.push((parent_key_a, parent));
+                    stack.push((parent_key_a, parent))?;
                 }
                 _ => return Err(Error::InvalidCode(code)),
             }
@@ -369,21 +1204,67 @@ impl CompiledMerkleProof {
         if stack.len() != 1 {
             return Err(Error::CorruptedStack);
         }
-        Ok(stack[0].1)
+        Ok(stack.pop().unwrap().1)
     }
 
+    /// Verify compiled merkle proof
+    /// see compute_root, the verification does not allocate on heap
     pub fn verify<H: Hasher + Default>(
         &self,
         root: &H256,
         leaves: Vec<(H256, H256)>,
     ) -> Result<bool> {
+        trace_span!("verify", leaves = leaves.len(), proof_size = self.0.len());
+        if root.is_zero() {
+            return verify_zero_root(&leaves);
+        }
         let calculated_root = self.compute_root::<H>(leaves)?;
         Ok(&calculated_root == root)
     }
 }
 
-impl Into<Vec<u8>> for CompiledMerkleProof {
-    fn into(self) -> Vec<u8> {
-        self.0
+/// A fixed size stack of (key, node)
+struct Stack {
+    items: [(H256, H256); MAX_STACK_SIZE],
+    len: usize,
+}
+
+impl Stack {
+    fn new() -> Self {
+        Stack {
+            items: [(H256::zero(), H256::zero()); MAX_STACK_SIZE],
+            len: 0,
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn push(&mut self, item: (H256, H256)) -> Result<()> {
+        if self.len >= MAX_STACK_SIZE {
+            return Err(Error::CorruptedStack);
+        }
+        self.items[self.len] = item;
+        self.len += 1;
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Option<(H256, H256)> {
+        if self.is_empty() {
+            return None;
+        }
+        self.len -= 1;
+        Some(self.items[self.len])
+    }
+}
+
+impl From<CompiledMerkleProof> for Vec<u8> {
+    fn from(val: CompiledMerkleProof) -> Self {
+        val.0
     }
 }
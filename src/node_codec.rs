@@ -0,0 +1,59 @@
+use crate::{traits::Hasher, H256};
+use core::marker::PhantomData;
+
+/// Trait for customize how a branch is fed to the hasher
+///
+/// height is the height of the branch, node_key is the common path of the
+/// subtree (bits below height + 1 are zero), lhs and rhs are the non-zero children
+pub trait NodeCodec {
+    fn write_branch<H: Hasher + ?Sized>(
+        hasher: &mut H,
+        height: u8,
+        node_key: &H256,
+        lhs: &H256,
+        rhs: &H256,
+    );
+}
+
+/// The default encoding: hash(lhs | rhs)
+pub struct DefaultCodec;
+
+impl NodeCodec for DefaultCodec {
+    fn write_branch<H: Hasher + ?Sized>(
+        hasher: &mut H,
+        _height: u8,
+        _node_key: &H256,
+        lhs: &H256,
+        rhs: &H256,
+    ) {
+        hasher.write_h256(lhs);
+        hasher.write_h256(rhs);
+    }
+}
+
+/// A hasher encodes branches by codec C, and hashes data by H
+pub struct CodecHasher<H, C> {
+    inner: H,
+    phantom: PhantomData<C>,
+}
+
+impl<H: Default, C> Default for CodecHasher<H, C> {
+    fn default() -> Self {
+        CodecHasher {
+            inner: H::default(),
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<H: Hasher, C: NodeCodec> Hasher for CodecHasher<H, C> {
+    fn write_h256(&mut self, h: &H256) {
+        self.inner.write_h256(h);
+    }
+    fn write_branch(&mut self, height: u8, node_key: &H256, lhs: &H256, rhs: &H256) {
+        C::write_branch(&mut self.inner, height, node_key, lhs, rhs);
+    }
+    fn finish(self) -> H256 {
+        self.inner.finish()
+    }
+}
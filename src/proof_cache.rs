@@ -0,0 +1,172 @@
+use crate::{
+    error::Result,
+    merkle_proof::MerkleProof,
+    traits::{Hasher, Store, Value},
+    tree::SparseMerkleTree,
+    H256,
+};
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+/// Default max number of cached proofs
+pub const DEFAULT_CAPACITY: usize = 1024;
+
+#[derive(Debug)]
+struct CacheEntry {
+    proof: MerkleProof,
+    inserted_at: Instant,
+    last_used: u64,
+}
+
+/// Source of the current time, a mock clock makes the expiration deterministic in tests
+pub trait Clock {
+    fn now(&self) -> Instant;
+}
+
+/// The real clock, `Instant::now`
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// A cache of merkle proofs keyed by root and keys
+///
+/// the proofs are always valid for the root they are stored with,
+/// entries older than the ttl are expired,
+/// and the least recently used entry is evicted when the cache is full
+#[derive(Debug)]
+pub struct ProofCache<C = SystemClock> {
+    capacity: usize,
+    ttl: Option<Duration>,
+    entries: HashMap<(H256, Vec<H256>), CacheEntry>,
+    // increase on each access, used to find the least recently used entry
+    tick: u64,
+    clock: C,
+}
+
+impl Default for ProofCache {
+    fn default() -> Self {
+        ProofCache::new(DEFAULT_CAPACITY)
+    }
+}
+
+impl ProofCache {
+    /// Create a cache holds at most capacity proofs, entries never expire
+    pub fn new(capacity: usize) -> Self {
+        ProofCache::with_clock(capacity, None, SystemClock)
+    }
+
+    /// Create a cache with DEFAULT_CAPACITY, entries older than ttl are expired
+    pub fn with_ttl(ttl: Duration) -> Self {
+        ProofCache::with_clock(DEFAULT_CAPACITY, Some(ttl), SystemClock)
+    }
+}
+
+impl<C: Clock> ProofCache<C> {
+    /// Create a cache reads the time from clock, entries older than ttl are expired
+    pub fn with_clock(capacity: usize, ttl: Option<Duration>, clock: C) -> Self {
+        ProofCache {
+            capacity,
+            ttl,
+            entries: HashMap::new(),
+            tick: 0,
+            clock,
+        }
+    }
+
+    /// Number of cached proofs, including expired ones which are not evicted yet
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Check empty of the cache
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Remove all cached proofs
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Get a cached proof of keys under root
+    /// return None if the proof is not cached or expired
+    pub fn get(&mut self, root: &H256, keys: &[H256]) -> Option<MerkleProof> {
+        let mut keys = keys.to_vec();
+        keys.sort_unstable();
+        let cache_key = (*root, keys);
+        let expired = match self.entries.get(&cache_key) {
+            Some(entry) => self.is_expired(entry),
+            None => return None,
+        };
+        if expired {
+            self.entries.remove(&cache_key);
+            return None;
+        }
+        self.tick += 1;
+        let entry = self.entries.get_mut(&cache_key)?;
+        entry.last_used = self.tick;
+        Some(entry.proof.clone())
+    }
+
+    /// Cache proof of keys under root
+    pub fn insert(&mut self, root: H256, mut keys: Vec<H256>, proof: MerkleProof) {
+        if self.capacity == 0 {
+            return;
+        }
+        keys.sort_unstable();
+        self.tick += 1;
+        let entry = CacheEntry {
+            proof,
+            inserted_at: self.clock.now(),
+            last_used: self.tick,
+        };
+        self.entries.insert((root, keys), entry);
+        self.evict();
+    }
+
+    /// Get merkle proof of keys from the cache, or generate it from the tree
+    pub fn merkle_proof<H: Hasher + Default, V: Value, S: Store<V>>(
+        &mut self,
+        tree: &SparseMerkleTree<H, V, S>,
+        keys: Vec<H256>,
+    ) -> Result<MerkleProof> {
+        if let Some(proof) = self.get(tree.root(), &keys) {
+            return Ok(proof);
+        }
+        let proof = tree.merkle_proof(keys.clone())?;
+        self.insert(*tree.root(), keys, proof.clone());
+        Ok(proof)
+    }
+
+    fn is_expired(&self, entry: &CacheEntry) -> bool {
+        match self.ttl {
+            Some(ttl) => self.clock.now().duration_since(entry.inserted_at) >= ttl,
+            None => false,
+        }
+    }
+
+    // remove expired entries, then remove least recently used entries until the cache is not overflow
+    fn evict(&mut self) {
+        if let Some(ttl) = self.ttl {
+            let now = self.clock.now();
+            self.entries
+                .retain(|_key, entry| now.duration_since(entry.inserted_at) < ttl);
+        }
+        while self.entries.len() > self.capacity {
+            let lru_key = self
+                .entries
+                .iter()
+                .min_by_key(|(_key, entry)| entry.last_used)
+                .map(|(key, _entry)| key.clone())
+                .expect("not empty");
+            self.entries.remove(&lru_key);
+        }
+    }
+}
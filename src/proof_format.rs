@@ -0,0 +1,149 @@
+use crate::{
+    error::{Error, Result},
+    merge::{hash_leaf, merge_branch},
+    traits::Hasher,
+    H256,
+};
+
+/// Version of the serialized proof format written by MerkleProof::serialize
+pub const PROOF_VERSION: u8 = 2;
+
+/// Versions of serialized proof format can be read
+/// 2: the first versioned format, proofs serialized before it have no version byte
+pub const SUPPORTED_VERSIONS: [u8; 1] = [2];
+
+pub(crate) fn check_version(version: u8) -> Result<()> {
+    if !SUPPORTED_VERSIONS.contains(&version) {
+        return Err(Error::UnsupportedProofVersion(version));
+    }
+    Ok(())
+}
+
+pub(crate) fn check_hasher_id<H: Hasher>(id: u8) -> Result<()> {
+    if id != H::ID {
+        return Err(Error::HasherMismatch {
+            expected: H::ID,
+            actual: id,
+        });
+    }
+    Ok(())
+}
+
+/// Verify a serialized proof of a single key without heap allocation, for targets without an allocator
+/// it is available without the alloc feature, the proof is read in place, see MerkleProof::serialize for the layout
+///
+/// the result equals to MerkleProof::deserialize then verify, except the kind of parse errors,
+/// return IncorrectNumberOfLeaves error if the proof is not of a single key
+pub fn verify_single_no_alloc<H: Hasher + Default>(
+    proof_bytes: &[u8],
+    root: &[u8; 32],
+    key: &[u8; 32],
+    value: &[u8; 32],
+) -> Result<bool> {
+    let mut reader = ProofReader {
+        bytes: proof_bytes,
+        offset: 0,
+    };
+    let version = reader.read(1)?[0];
+    check_version(version)?;
+    check_hasher_id::<H>(reader.read(1)?[0])?;
+    let leaves_count = reader.read_u32()? as usize;
+    // only the first path is used, the others are parsed to check the layout
+    let mut path: &[u8] = &[];
+    for i in 0..leaves_count {
+        let len = reader.read_u16()? as usize;
+        let leaf_path = reader.read(len)?;
+        if i == 0 {
+            path = leaf_path;
+        }
+    }
+    let proof_count = reader.read_u32()? as usize;
+    let mut proof = reader
+        .read(proof_count.saturating_mul(33))?
+        .chunks_exact(33);
+    if reader.offset != proof_bytes.len() {
+        return Err(Error::CorruptedProof);
+    }
+
+    let value: H256 = (*value).into();
+    // a zero root is the empty tree, the proof is not used
+    if *root == [0u8; 32] {
+        return Ok(value.is_zero());
+    }
+    if leaves_count != 1 {
+        return Err(Error::IncorrectNumberOfLeaves {
+            expected: leaves_count,
+            actual: 1,
+        });
+    }
+
+    // same steps as compute_root_from_paths with one leaf
+    let mut key: H256 = (*key).into();
+    let mut node = hash_leaf::<H>(&key, &value);
+    let mut height = 0u8;
+    while let Some(item) = proof.next() {
+        // skip zeros
+        if let Some(&merge_height) = path.first() {
+            if height != merge_height {
+                key = key.copy_bits(merge_height);
+                height = merge_height;
+            }
+        }
+        let mut sibling = [0u8; 32];
+        sibling.copy_from_slice(&item[..32]);
+        let sibling: H256 = sibling.into();
+        if height < item[32] {
+            height = item[32];
+        }
+        let parent_key = key.parent_path(height);
+        node = if key.get_bit(height) {
+            merge_branch::<H>(height, &parent_key, &sibling, &node)
+        } else {
+            merge_branch::<H>(height, &parent_key, &node, &sibling)
+        };
+        if height == u8::MAX {
+            if proof.len() != 0 {
+                return Err(Error::CorruptedProof);
+            }
+            break;
+        }
+        if !path.is_empty() {
+            path = &path[1..];
+        }
+        key = parent_key;
+        height += 1;
+    }
+    Ok(node.as_slice() == root)
+}
+
+// read fields of a serialized proof
+pub(crate) struct ProofReader<'a> {
+    pub(crate) bytes: &'a [u8],
+    pub(crate) offset: usize,
+}
+
+impl<'a> ProofReader<'a> {
+    pub(crate) fn read(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.bytes.len() - self.offset < len {
+            return Err(Error::TruncatedProof {
+                expected: self.offset.saturating_add(len),
+                actual: self.bytes.len(),
+            });
+        }
+        let data = &self.bytes[self.offset..self.offset + len];
+        self.offset += len;
+        Ok(data)
+    }
+
+    pub(crate) fn read_u16(&mut self) -> Result<u16> {
+        let mut buf = [0u8; 2];
+        buf.copy_from_slice(self.read(2)?);
+        Ok(u16::from_le_bytes(buf))
+    }
+
+    pub(crate) fn read_u32(&mut self) -> Result<u32> {
+        let mut buf = [0u8; 4];
+        buf.copy_from_slice(self.read(4)?);
+        Ok(u32::from_le_bytes(buf))
+    }
+}
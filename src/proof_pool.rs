@@ -0,0 +1,97 @@
+use crate::{
+    error::Result,
+    merkle_proof::{verify_zero_root, MerkleProof},
+    traits::Hasher,
+    vec::Vec,
+    H256,
+};
+use core::{cell::RefCell, ops::Deref};
+
+/// A pool of reusable merkle proof buffers, for verifiers deserializing many proofs
+///
+/// a buffer is handed out empty by `get` and returned to the pool when the PooledProof is dropped,
+/// at most capacity buffers are kept
+#[derive(Debug)]
+pub struct ProofPool {
+    capacity: usize,
+    free: RefCell<Vec<MerkleProof>>,
+}
+
+impl ProofPool {
+    /// Create a pool keeps at most capacity free buffers
+    pub fn new(capacity: usize) -> Self {
+        ProofPool {
+            capacity,
+            free: RefCell::new(Vec::with_capacity(capacity)),
+        }
+    }
+
+    /// Number of free buffers in the pool
+    pub fn free_count(&self) -> usize {
+        self.free.borrow().len()
+    }
+
+    /// Take a buffer from the pool, or allocate a new one if the pool is empty
+    pub fn get(&self) -> PooledProof<'_> {
+        let proof = self
+            .free
+            .borrow_mut()
+            .pop()
+            .unwrap_or_else(|| MerkleProof::new(Vec::new(), Vec::new()));
+        PooledProof {
+            pool: self,
+            proof: Some(proof),
+        }
+    }
+}
+
+/// A merkle proof buffer borrowed from ProofPool
+#[derive(Debug)]
+pub struct PooledProof<'a> {
+    pool: &'a ProofPool,
+    // always Some until dropped
+    proof: Option<MerkleProof>,
+}
+
+impl PooledProof<'_> {
+    /// Deserialize bytes into the buffer, see MerkleProof::deserialize
+    /// the buffer is cleared on error, so a previous proof is never verified
+    pub fn deserialize<H: Hasher>(&mut self, bytes: &[u8]) -> Result<()> {
+        self.proof
+            .as_mut()
+            .expect("pooled proof")
+            .deserialize_into::<H>(bytes)
+    }
+
+    /// Verify the buffered proof, see MerkleProof::verify
+    pub fn verify<H: Hasher + Default>(
+        &self,
+        root: &H256,
+        leaves: &[(H256, H256)],
+    ) -> Result<bool> {
+        if root.is_zero() {
+            return verify_zero_root(leaves);
+        }
+        Ok(&self.compute_root_ref::<H>(leaves)? == root)
+    }
+}
+
+impl Deref for PooledProof<'_> {
+    type Target = MerkleProof;
+
+    fn deref(&self) -> &MerkleProof {
+        self.proof.as_ref().expect("pooled proof")
+    }
+}
+
+impl Drop for PooledProof<'_> {
+    fn drop(&mut self) {
+        if let Some(mut proof) = self.proof.take() {
+            let mut free = self.pool.free.borrow_mut();
+            if free.len() < self.pool.capacity {
+                proof.clear();
+                free.push(proof);
+            }
+        }
+    }
+}
@@ -0,0 +1,65 @@
+use crate::{
+    error::{Error, Result},
+    merkle_proof::MerkleProof,
+    traits::Hasher,
+    vec::Vec,
+    H256,
+};
+use core::marker::PhantomData;
+
+/// A partial state authenticated by a merkle proof
+///
+/// the leaves of the proof can be updated locally and the root is recomputed
+/// from the proof, the siblings don't depend on the values of the proven leaves
+#[derive(Debug, Clone)]
+pub struct ProofState<H> {
+    proof: MerkleProof,
+    leaves: Vec<(H256, H256)>,
+    root: H256,
+    phantom: PhantomData<H>,
+}
+
+impl<H: Hasher + Default> ProofState<H> {
+    /// Build state from a proof and its leaves, return VerificationFailed error if the proof is invalid
+    pub fn new(proof: MerkleProof, root: H256, leaves: Vec<(H256, H256)>) -> Result<Self> {
+        if proof.clone().compute_root_borrowed::<H>(&leaves)? != root {
+            return Err(Error::VerificationFailed);
+        }
+        Ok(ProofState {
+            proof,
+            leaves,
+            root,
+            phantom: PhantomData,
+        })
+    }
+
+    /// Root of the current state
+    pub fn root(&self) -> &H256 {
+        &self.root
+    }
+
+    /// Get value of a proven key, return NonExistentKey error if key is not covered by the proof
+    pub fn get(&self, key: &H256) -> Result<H256> {
+        self.leaves
+            .iter()
+            .find(|(k, _v)| k == key)
+            .map(|(_k, v)| *v)
+            .ok_or(Error::NonExistentKey(*key))
+    }
+
+    /// Set value of a proven key, return the new root
+    /// return NonExistentKey error if key is not covered by the proof
+    pub fn set(&mut self, key: H256, value: H256) -> Result<&H256> {
+        let leaf = self
+            .leaves
+            .iter_mut()
+            .find(|(k, _v)| k == &key)
+            .ok_or(Error::NonExistentKey(key))?;
+        leaf.1 = value;
+        self.root = self
+            .proof
+            .clone()
+            .compute_root_borrowed::<H>(&self.leaves)?;
+        Ok(&self.root)
+    }
+}
@@ -0,0 +1,82 @@
+use crate::{
+    error::Result,
+    merkle_proof::MerkleProof,
+    sync::Arc,
+    traits::{Hasher, Store, Value},
+    tree::{BranchNode, LeafNode, SparseMerkleTree},
+    vec::Vec,
+    H256,
+};
+
+/// A read-only view of a tree, see `SparseMerkleTree::snapshot`
+///
+/// the snapshot owns a copy of the tree, so it is unaffected by later writes to the tree,
+/// clone of a snapshot is cheap and shares the copy
+///
+/// taking a snapshot clones the store, which is O(n) for DefaultStore,
+/// store the tree in an `Arc<S>` to share the store instead, the writer copies it on the next write
+#[derive(Debug)]
+pub struct SmtSnapshot<H, V, S> {
+    tree: Arc<SparseMerkleTree<H, V, S>>,
+}
+
+impl<H, V, S> Clone for SmtSnapshot<H, V, S> {
+    fn clone(&self) -> Self {
+        SmtSnapshot {
+            tree: Arc::clone(&self.tree),
+        }
+    }
+}
+
+impl<H: Hasher + Default, V: Value, S: Store<V>> SmtSnapshot<H, V, S> {
+    pub(crate) fn new(tree: SparseMerkleTree<H, V, S>) -> Self {
+        SmtSnapshot {
+            tree: Arc::new(tree),
+        }
+    }
+
+    /// Merkle root of the snapshot
+    pub fn root(&self) -> &H256 {
+        self.tree.root()
+    }
+
+    /// Get value of a leaf, see `SparseMerkleTree::get`
+    pub fn get(&self, key: &H256) -> Result<V> {
+        self.tree.get(key)
+    }
+
+    /// Generate merkle proof against the snapshot root, see `SparseMerkleTree::merkle_proof`
+    pub fn merkle_proof(&self, keys: Vec<H256>) -> Result<MerkleProof> {
+        self.tree.merkle_proof(keys)
+    }
+}
+
+/// A shared store, copy on write
+///
+/// reads are forwarded to the store, writes copy the store first if it is shared by snapshots
+impl<V, S: Store<V> + Clone> Store<V> for Arc<S> {
+    fn get_branch(&self, node: &H256) -> Result<Option<BranchNode>> {
+        self.as_ref().get_branch(node)
+    }
+    fn get_leaf(&self, leaf_hash: &H256) -> Result<Option<LeafNode<V>>> {
+        self.as_ref().get_leaf(leaf_hash)
+    }
+    fn insert_branch(&mut self, node: H256, branch: BranchNode) -> Result<()> {
+        Arc::make_mut(self).insert_branch(node, branch)
+    }
+    fn insert_leaf(&mut self, leaf_hash: H256, leaf: LeafNode<V>) -> Result<()> {
+        Arc::make_mut(self).insert_leaf(leaf_hash, leaf)
+    }
+    fn remove_branch(&mut self, node: &H256) -> Result<()> {
+        Arc::make_mut(self).remove_branch(node)
+    }
+    fn remove_leaf(&mut self, leaf_hash: &H256) -> Result<()> {
+        Arc::make_mut(self).remove_leaf(leaf_hash)
+    }
+    fn leaf_ref(&self, leaf_hash: &H256) -> Result<Option<&LeafNode<V>>> {
+        self.as_ref().leaf_ref(leaf_hash)
+    }
+    fn take_leaf(&mut self, leaf_hash: &H256) -> Result<Option<LeafNode<V>>> {
+        Arc::make_mut(self).take_leaf(leaf_hash)
+    }
+}
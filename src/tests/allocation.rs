@@ -0,0 +1,155 @@
+use crate::*;
+use crate::{blake2b::Blake2bHasher, default_store::DefaultStore, SparseMerkleTree};
+use rand::prelude::Rng;
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
+};
+
+type SMT = SparseMerkleTree<Blake2bHasher, H256, DefaultStore<H256>>;
+
+/// A global allocator counts allocations of each thread,
+/// tests run in parallel so counters must be thread local
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+/// Run f and return the number of heap allocations in current thread
+pub fn count_allocations<T>(f: impl FnOnce() -> T) -> (T, usize) {
+    let start = ALLOCATIONS.with(Cell::get);
+    let result = f();
+    let end = ALLOCATIONS.with(Cell::get);
+    (result, end - start)
+}
+
+fn random_smt(n: usize) -> (SMT, Vec<(H256, H256)>) {
+    let mut rng = rand::thread_rng();
+    let mut smt = SMT::default();
+    let mut pairs = Vec::with_capacity(n);
+    for _ in 0..n {
+        let key: H256 = rng.gen::<[u8; 32]>().into();
+        let value: H256 = rng.gen::<[u8; 32]>().into();
+        smt.update(key, value).expect("update");
+        pairs.push((key, value));
+    }
+    (smt, pairs)
+}
+
+#[test]
+fn test_compiled_proof_verify_no_allocation() {
+    let (smt, pairs) = random_smt(100);
+    for leaves in [&pairs[..1], &pairs[..20], &pairs[..]].iter() {
+        let keys = leaves.iter().map(|(k, _v)| *k).collect();
+        let proof = smt.merkle_proof(keys).expect("proof");
+        let compiled_proof = proof.compile(leaves.to_vec()).expect("compile");
+        let leaves = leaves.to_vec();
+        let (valid, allocations) = count_allocations(|| {
+            compiled_proof
+                .verify::<Blake2bHasher>(smt.root(), leaves)
+                .expect("verify")
+        });
+        assert!(valid);
+        assert_eq!(allocations, 0);
+    }
+}
+
+/// Upper bound of allocations of a single leaf `MerkleProof::verify`, currently reached by
+/// the buffer of leaves collected into the tree buffer, the tree buffer node,
+/// and the slices of leaves path
+const SINGLE_LEAF_VERIFY_ALLOCATIONS: usize = 3;
+
+#[test]
+fn test_merkle_proof_verify_allocations() {
+    let (smt, pairs) = random_smt(100);
+    for leaf in pairs.iter().take(10) {
+        let proof = smt.merkle_proof(vec![leaf.0]).expect("proof");
+        let leaves = vec![*leaf];
+        let (valid, allocations) = count_allocations(|| {
+            proof
+                .verify::<Blake2bHasher>(smt.root(), leaves)
+                .expect("verify")
+        });
+        assert!(valid);
+        assert!(
+            allocations <= SINGLE_LEAF_VERIFY_ALLOCATIONS,
+            "verify allocates {} times",
+            allocations
+        );
+    }
+}
+
+#[test]
+fn test_verify_single_no_alloc() {
+    use crate::error::Error;
+
+    let (smt, pairs) = random_smt(100);
+    let absent: H256 = rand::thread_rng().gen::<[u8; 32]>().into();
+    let (single_smt, single_pairs) = random_smt(1);
+    let root: [u8; 32] = (*smt.root()).into();
+    let cases = pairs
+        .iter()
+        .take(10)
+        .map(|leaf| (&smt, *leaf))
+        .chain(Some((&smt, (absent, H256::zero()))))
+        .chain(Some((&single_smt, single_pairs[0])));
+    for (smt, (key, value)) in cases {
+        let bytes = smt
+            .merkle_proof(vec![key])
+            .expect("proof")
+            .serialize::<Blake2bHasher>();
+        let root: [u8; 32] = (*smt.root()).into();
+        let (key, value): ([u8; 32], [u8; 32]) = (key.into(), value.into());
+        let (valid, allocations) = count_allocations(|| {
+            verify_single_no_alloc::<Blake2bHasher>(&bytes, &root, &key, &value).expect("verify")
+        });
+        assert!(valid);
+        assert_eq!(allocations, 0);
+        let wrong_value = [1u8; 32];
+        assert!(
+            !verify_single_no_alloc::<Blake2bHasher>(&bytes, &root, &key, &wrong_value)
+                .expect("verify")
+        );
+    }
+
+    let keys = pairs.iter().take(2).map(|(k, _v)| *k).collect();
+    let bytes = smt
+        .merkle_proof(keys)
+        .expect("proof")
+        .serialize::<Blake2bHasher>();
+    let key: [u8; 32] = pairs[0].0.into();
+    let value: [u8; 32] = pairs[0].1.into();
+    assert_eq!(
+        verify_single_no_alloc::<Blake2bHasher>(&bytes, &root, &key, &value),
+        Err(Error::IncorrectNumberOfLeaves {
+            expected: 2,
+            actual: 1
+        })
+    );
+    let bytes = smt
+        .merkle_proof(vec![pairs[0].0])
+        .expect("proof")
+        .serialize::<Blake2bHasher>();
+    assert_eq!(
+        verify_single_no_alloc::<Blake2bHasher>(&bytes[..bytes.len() - 1], &root, &key, &value),
+        Err(Error::TruncatedProof {
+            expected: bytes.len(),
+            actual: bytes.len() - 1
+        })
+    );
+}
@@ -0,0 +1,77 @@
+use crate::*;
+use crate::{blake2b::Blake2bHasher, default_store::DefaultStore, error::Error};
+
+type SMT = SparseMerkleTree<Blake2bHasher, H256, DefaultStore<H256>>;
+
+fn check_proofs(smt: &SMT, leaves: &[(H256, H256)]) {
+    // all leaves, each leaf, and pairs of neighbours
+    let mut key_sets: Vec<Vec<(H256, H256)>> = vec![leaves.to_vec()];
+    key_sets.extend(leaves.iter().map(|leaf| vec![*leaf]));
+    key_sets.extend(leaves.windows(2).map(|pair| pair.to_vec()));
+    for leaves in key_sets {
+        let keys = leaves.iter().map(|(k, _v)| *k).collect();
+        let proof = smt.merkle_proof(keys).expect("proof");
+        assert!(proof
+            .clone()
+            .verify::<Blake2bHasher>(smt.root(), leaves.clone())
+            .expect("verify"));
+        let compiled_proof = proof.compile(leaves.clone()).expect("compile");
+        assert!(compiled_proof
+            .verify::<Blake2bHasher>(smt.root(), leaves)
+            .expect("verify compiled"));
+    }
+}
+
+#[test]
+fn test_empty_keys() {
+    let smt = SMT::default();
+    assert_eq!(smt.merkle_proof(vec![]), Err(Error::EmptyKeys));
+    let mut smt = SMT::default();
+    smt.update([1u8; 32].into(), [1u8; 32].into())
+        .expect("update");
+    assert_eq!(smt.merkle_proof(vec![]), Err(Error::EmptyKeys));
+
+    let proof = smt.merkle_proof(vec![[1u8; 32].into()]).expect("proof");
+    assert_eq!(
+        proof.clone().verify::<Blake2bHasher>(smt.root(), vec![]),
+        Err(Error::EmptyKeys)
+    );
+    assert_eq!(proof.compile(vec![]).map(|_| ()), Err(Error::EmptyKeys));
+}
+
+#[test]
+fn test_keys_differ_in_lowest_bits() {
+    // 256 keys share the top 248 bits
+    let leaves: Vec<(H256, H256)> = (0..=255u8)
+        .map(|i| {
+            let mut key = [0xAAu8; 32];
+            key[0] = i;
+            (key.into(), [1u8; 32].into())
+        })
+        .collect();
+    let mut smt = SMT::default();
+    smt.update_all(leaves.clone()).expect("update");
+    check_proofs(&smt, &leaves);
+}
+
+#[test]
+fn test_keys_differ_at_one_bit() {
+    // 256 keys differ with the base key at a single bit, and the base key itself
+    let base: H256 = [0x55u8; 32].into();
+    let mut leaves: Vec<(H256, H256)> = (0..=255u8)
+        .map(|i| {
+            let mut key = base;
+            if key.get_bit(i) {
+                key.clear_bit(i);
+            } else {
+                key.set_bit(i);
+            }
+            (key, [1u8; 32].into())
+        })
+        .collect();
+    leaves.push((base, [2u8; 32].into()));
+    leaves.sort_unstable_by_key(|(k, _v)| *k);
+    let mut smt = SMT::default();
+    smt.update_all(leaves.clone()).expect("update");
+    check_proofs(&smt, &leaves);
+}
@@ -0,0 +1,72 @@
+use crate::*;
+use crate::{default_store::DefaultStore, fn_hasher::FnHasher, traits::Hasher, SparseMerkleTree};
+
+/// A non-cryptographic hash, only for tests
+fn trivial_hash(data: &[u8]) -> [u8; 32] {
+    let mut hash = [0u8; 32];
+    for (i, byte) in data.iter().enumerate() {
+        let pos = i % 32;
+        hash[pos] = hash[pos].wrapping_mul(31).wrapping_add(*byte) ^ (i as u8);
+    }
+    hash
+}
+
+type HashFn = fn(&[u8]) -> [u8; 32];
+
+/// Wraps FnHasher to implement Default
+struct TrivialHasher(FnHasher<HashFn>);
+
+impl Default for TrivialHasher {
+    fn default() -> Self {
+        TrivialHasher(FnHasher::new(trivial_hash))
+    }
+}
+
+impl Hasher for TrivialHasher {
+    fn write_h256(&mut self, h: &H256) {
+        self.0.write_h256(h);
+    }
+    fn finish(self) -> H256 {
+        self.0.finish()
+    }
+}
+
+#[test]
+fn test_fn_hasher_buffer() {
+    let a: H256 = [1u8; 32].into();
+    let b: H256 = [2u8; 32].into();
+    let mut hasher = FnHasher::new(|data: &[u8]| {
+        assert_eq!(data.len(), 64);
+        trivial_hash(data)
+    });
+    hasher.write_h256(&a);
+    hasher.write_h256(&b);
+
+    let mut data = [0u8; 64];
+    data[..32].copy_from_slice(a.as_slice());
+    data[32..].copy_from_slice(b.as_slice());
+    assert_eq!(hasher.finish(), trivial_hash(&data).into());
+}
+
+#[test]
+fn test_fn_hasher_tree() {
+    let mut tree = SparseMerkleTree::<TrivialHasher, H256, DefaultStore<H256>>::default();
+    let pairs: Vec<(H256, H256)> = (1u8..=10)
+        .map(|i| ([i; 32].into(), [i + 1; 32].into()))
+        .collect();
+    for (k, v) in &pairs {
+        tree.update(*k, *v).expect("update");
+    }
+    let proof = tree
+        .merkle_proof(pairs.iter().map(|(k, _v)| *k).collect())
+        .expect("proof");
+    assert!(proof
+        .clone()
+        .verify::<TrivialHasher>(tree.root(), pairs.clone())
+        .expect("verify"));
+    let mut bad_pairs = pairs;
+    bad_pairs[0].1 = [42u8; 32].into();
+    assert!(!proof
+        .verify::<TrivialHasher>(tree.root(), bad_pairs)
+        .expect("verify"));
+}
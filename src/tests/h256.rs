@@ -0,0 +1,199 @@
+use crate::H256;
+use proptest::prelude::*;
+use std::io::Read;
+
+#[test]
+fn test_h256_as_mut_bytes() {
+    let data: Vec<u8> = (0u8..32).collect();
+    let mut reader = &data[..];
+    let mut h = H256::zero();
+    reader.read_exact(h.as_mut_bytes()).expect("read");
+
+    let mut expected = [0u8; 32];
+    expected.copy_from_slice(&data);
+    assert_eq!(h, H256::from(expected));
+}
+
+#[test]
+fn test_h256_checked_next_prev_boundary() {
+    let max: H256 = [255u8; 32].into();
+    assert_eq!(max.checked_next(), None);
+    assert_eq!(H256::zero().checked_prev(), None);
+
+    let mut one = [0u8; 32];
+    one[0] = 1;
+    assert_eq!(H256::zero().checked_next(), Some(one.into()));
+    assert_eq!(H256::from(one).checked_prev(), Some(H256::zero()));
+
+    let mut below_max = [255u8; 32];
+    below_max[0] = 254;
+    assert_eq!(max.checked_prev(), Some(below_max.into()));
+
+    // carry to the heigher byte
+    let mut carry = [0u8; 32];
+    carry[0] = 255;
+    let mut expected = [0u8; 32];
+    expected[1] = 1;
+    assert_eq!(H256::from(carry).checked_next(), Some(expected.into()));
+}
+
+proptest! {
+    #[test]
+    fn test_h256_checked_next_prev(key: [u8; 32]) {
+        let key: H256 = key.into();
+        if let Some(next) = key.checked_next() {
+            assert!(next > key);
+            assert_eq!(next.checked_prev(), Some(key));
+        }
+        if let Some(prev) = key.checked_prev() {
+            assert!(prev < key);
+            assert_eq!(prev.checked_next(), Some(key));
+        }
+    }
+}
+
+#[test]
+fn test_from_hex() {
+    let mut bytes = [0u8; 32];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = i as u8 * 7;
+    }
+    let s = hex::encode(bytes);
+    assert_eq!(H256::from_hex(&s), Ok(bytes.into()));
+    assert_eq!(H256::from_hex(&s.to_uppercase()), Ok(bytes.into()));
+    assert_eq!(H256::from_hex(&format!("0x{}", s)), Ok(bytes.into()));
+    assert_eq!(
+        H256::from_hex(&s[1..]),
+        Err(crate::error::Error::InvalidHexLength(63))
+    );
+    assert_eq!(
+        H256::from_hex(&format!("{}g", &s[1..])),
+        Err(crate::error::Error::InvalidHexChar('g'))
+    );
+}
+
+#[test]
+fn test_copy_bits_into() {
+    let h: H256 = [0xABu8; 32].into();
+    let mut dst: H256 = [0xFFu8; 32].into();
+    h.copy_bits_into(0..256, &mut dst);
+    assert_eq!(dst, h);
+    h.copy_bits_into(5..5, &mut dst);
+    assert_eq!(dst, H256::zero());
+    h.copy_bits_into(8..12, &mut dst);
+    let mut expected = [0u8; 32];
+    expected[1] = 0x0B;
+    assert_eq!(dst, expected.into());
+}
+
+proptest! {
+    #[test]
+    fn test_copy_bits_into_match_bits(key: [u8; 32], dst: [u8; 32], start in 0u16..=256, end in 0u16..=256) {
+        let key: H256 = key.into();
+        let mut dst: H256 = dst.into();
+        key.copy_bits_into(start..end, &mut dst);
+        for i in 0..=255u8 {
+            let in_range = start <= i as u16 && (i as u16) < end;
+            assert_eq!(dst.get_bit(i), in_range && key.get_bit(i));
+        }
+        if start < 256 {
+            let mut copied = H256::zero();
+            key.copy_bits_into(start..256, &mut copied);
+            assert_eq!(copied, key.copy_bits(start as u8));
+        }
+    }
+}
+
+#[test]
+fn test_count_ones_zeros() {
+    let max: H256 = [255u8; 32].into();
+    assert_eq!(max.count_ones(), 256);
+    assert_eq!(max.count_zeros(), 0);
+    assert_eq!(H256::zero().count_ones(), 0);
+    assert_eq!(H256::zero().count_zeros(), 256);
+    let mut h = H256::zero();
+    h.set_bit(0);
+    h.set_bit(255);
+    assert_eq!(h.count_ones(), 2);
+}
+
+proptest! {
+    #[test]
+    fn test_count_ones_plus_zeros(key: [u8; 32]) {
+        let key: H256 = key.into();
+        assert_eq!(key.count_ones() + key.count_zeros(), 256);
+        assert_eq!(key.count_ones(), (0..=255u8).filter(|i| key.get_bit(*i)).count() as u32);
+    }
+}
+
+#[test]
+fn test_deref_bytes() {
+    fn first_byte(data: &[u8]) -> u8 {
+        data[0]
+    }
+    let mut bytes = [0u8; 32];
+    bytes[0] = 42;
+    let key: H256 = bytes.into();
+    assert_eq!(first_byte(&key), 42);
+    assert_eq!(key.len(), 32);
+    assert_eq!(AsRef::<[u8]>::as_ref(&key), &bytes[..]);
+    assert_eq!(hex::encode(key), hex::encode(bytes));
+}
+
+#[test]
+fn test_mask_prefix() {
+    let max: H256 = [255u8; 32].into();
+    let masked = max.mask_prefix(8);
+    let mut expected = [0u8; 32];
+    expected[31] = 255;
+    assert_eq!(masked, expected.into());
+    assert_eq!(masked.count_ones(), 8);
+    assert!((248..=255u8).all(|i| masked.get_bit(i)));
+    assert_eq!(max.mask_prefix(0), H256::zero());
+    assert_eq!(max.mask_prefix(255).count_ones(), 255);
+    assert!(!max.mask_prefix(255).get_bit(0));
+}
+
+#[test]
+fn test_is_prefix_of() {
+    let mut a = [0u8; 32];
+    let mut b = [0u8; 32];
+    a[31] = 0b1010_1010;
+    b[31] = 0b1010_1010;
+    // differ at the 9th bit from the top
+    b[30] = 0b1000_0000;
+    let (a, b): (H256, H256) = (a.into(), b.into());
+    assert!(a.is_prefix_of(&b, 8));
+    assert!(!a.is_prefix_of(&b, 9));
+    assert!(b.is_prefix_of(&b, 9));
+    let mut c = b;
+    c.clear_bit(247);
+    assert!(a.is_prefix_of(&c, 9));
+    assert!(a.is_prefix_of(&H256::zero(), 0));
+}
+
+proptest! {
+    #[test]
+    fn test_reverse_involution(key: [u8; 32]) {
+        let key: H256 = key.into();
+        assert_eq!(key.reverse_bits().reverse_bits(), key);
+        assert_eq!(key.reverse_bytes().reverse_bytes(), key);
+        assert_eq!(key.reverse_bits().count_ones(), key.count_ones());
+    }
+}
+
+#[test]
+fn test_reverse_single_bit() {
+    for i in [0u8, 1, 7, 8, 100, 255] {
+        let mut key = H256::zero();
+        key.set_bit(i);
+        let mut mirrored = H256::zero();
+        mirrored.set_bit(255 - i);
+        assert_eq!(key.reverse_bits(), mirrored);
+    }
+    let mut bytes = [0u8; 32];
+    bytes[0] = 0b0000_0011;
+    let mut reversed = [0u8; 32];
+    reversed[31] = 0b0000_0011;
+    assert_eq!(H256::from(bytes).reverse_bytes(), reversed.into());
+}
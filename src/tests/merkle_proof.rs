@@ -0,0 +1,1090 @@
+use crate::*;
+use crate::{
+    blake2b::Blake2bHasher, default_store::DefaultStore, error::Error, traits::Hasher,
+    SparseMerkleTree,
+};
+
+type SMT = SparseMerkleTree<Blake2bHasher, H256, DefaultStore<H256>>;
+
+fn new_smt(pairs: Vec<(H256, H256)>) -> SMT {
+    let mut smt = SMT::default();
+    for (key, value) in pairs {
+        smt.update(key, value).unwrap();
+    }
+    smt
+}
+
+fn h256(n: u8) -> H256 {
+    let mut buf = [0u8; 32];
+    buf[0] = n;
+    buf[31] = n;
+    buf.into()
+}
+
+#[test]
+fn test_verify_threshold() {
+    let smt = new_smt((1..=3).map(|i| (h256(i), h256(i + 100))).collect());
+    let leaves: Vec<(H256, H256)> = (1..=5)
+        .map(|i| (h256(i), smt.get(&h256(i)).unwrap()))
+        .collect();
+    let proof = smt
+        .merkle_proof(leaves.iter().map(|(k, _v)| *k).collect())
+        .expect("proof");
+
+    assert!(proof
+        .clone()
+        .verify_threshold::<Blake2bHasher>(smt.root(), leaves.clone(), 3)
+        .expect("verify"));
+    assert!(!proof
+        .clone()
+        .verify_threshold::<Blake2bHasher>(smt.root(), leaves.clone(), 4)
+        .expect("verify"));
+
+    // claim an absent key is present
+    let mut leaves = leaves;
+    leaves[4].1 = h256(200);
+    assert!(!proof
+        .verify_threshold::<Blake2bHasher>(smt.root(), leaves, 3)
+        .expect("verify"));
+}
+
+#[test]
+fn test_verify_or_root() {
+    let smt = new_smt((1..=3).map(|i| (h256(i), h256(i + 100))).collect());
+    let proof = smt.merkle_proof(vec![h256(1)]).expect("proof");
+    assert_eq!(
+        proof
+            .clone()
+            .verify_or_root::<Blake2bHasher>(smt.root(), vec![(h256(1), h256(101))]),
+        Ok(Ok(()))
+    );
+
+    // derived root of a mismatched value is the root of the updated tree
+    let mut smt = smt;
+    let bad_root = *smt.root();
+    smt.update(h256(1), h256(42)).expect("update");
+    assert_eq!(
+        proof.verify_or_root::<Blake2bHasher>(&bad_root, vec![(h256(1), h256(42))]),
+        Ok(Err(*smt.root()))
+    );
+}
+
+#[test]
+fn test_serialize_proof() {
+    let smt = new_smt((1..=20).map(|i| (h256(i), h256(i + 100))).collect());
+    for keys in [vec![h256(1)], vec![h256(21)], (1..=25).map(h256).collect()].iter() {
+        let proof = smt.merkle_proof(keys.clone()).expect("proof");
+        let bytes = proof.serialize::<Blake2bHasher>();
+        assert_eq!(MerkleProof::deserialize::<Blake2bHasher>(&bytes), Ok(proof));
+
+        // truncated or trailing bytes
+        assert_eq!(
+            MerkleProof::deserialize::<Blake2bHasher>(&bytes[..bytes.len() - 1]),
+            Err(Error::CorruptedProof)
+        );
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert_eq!(
+            MerkleProof::deserialize::<Blake2bHasher>(&trailing),
+            Err(Error::CorruptedProof)
+        );
+    }
+    let empty_proof = MerkleProof::new(Vec::new(), Vec::new());
+    assert_eq!(
+        MerkleProof::deserialize::<Blake2bHasher>(&empty_proof.serialize::<Blake2bHasher>()),
+        Ok(empty_proof)
+    );
+}
+
+#[test]
+fn test_proof_verifier() {
+    let smt = new_smt((1..=20).map(|i| (h256(i), h256(i + 100))).collect());
+    let leaves: Vec<(H256, H256)> = (15..=25)
+        .map(|i| (h256(i), smt.get(&h256(i)).unwrap()))
+        .collect();
+    let proof = smt
+        .merkle_proof(leaves.iter().map(|(k, _v)| *k).collect())
+        .expect("proof");
+    let bytes = proof.serialize::<Blake2bHasher>();
+
+    let mut verifier = ProofVerifier::new();
+    for chunk in bytes.chunks(3) {
+        verifier.feed(chunk).expect("feed");
+    }
+    assert!(verifier
+        .clone()
+        .finalize::<Blake2bHasher>(smt.root(), leaves.clone())
+        .expect("finalize"));
+    assert_eq!(verifier.feed(&[0]), Err(Error::CorruptedProof));
+
+    // finalize an incomplete proof
+    let mut verifier = ProofVerifier::new();
+    verifier.feed(&bytes[..bytes.len() - 3]).expect("feed");
+    assert_eq!(
+        verifier.finalize::<Blake2bHasher>(smt.root(), leaves),
+        Err(Error::CorruptedProof)
+    );
+}
+
+#[test]
+fn test_verify_transition() {
+    let mut smt = new_smt((1..=20).map(|i| (h256(i), h256(i + 100))).collect());
+    let old_root = *smt.root();
+    let changes: Vec<(H256, H256, H256)> = vec![
+        (h256(3), h256(103), h256(200)),
+        (h256(10), h256(110), H256::zero()),
+        (h256(30), H256::zero(), h256(230)),
+    ];
+    let proof = smt
+        .merkle_proof(changes.iter().map(|(k, _old, _new)| *k).collect())
+        .expect("proof");
+    for (key, _old_value, new_value) in &changes {
+        smt.update(*key, *new_value).expect("update");
+    }
+    let new_root = *smt.root();
+
+    assert!(proof
+        .verify_transition::<Blake2bHasher>(&old_root, &new_root, changes.clone())
+        .expect("verify"));
+    // wrong roots
+    assert!(!proof
+        .verify_transition::<Blake2bHasher>(&new_root, &new_root, changes.clone())
+        .expect("verify"));
+    assert!(!proof
+        .verify_transition::<Blake2bHasher>(&old_root, &old_root, changes.clone())
+        .expect("verify"));
+    // wrong post value
+    let mut wrong_changes = changes;
+    wrong_changes[0].2 = h256(201);
+    assert!(!proof
+        .verify_transition::<Blake2bHasher>(&old_root, &new_root, wrong_changes)
+        .expect("verify"));
+}
+
+#[test]
+fn test_verify_hex() {
+    let smt = new_smt((1..=20).map(|i| (h256(i), h256(i + 100))).collect());
+    let proof = smt.merkle_proof(vec![h256(5)]).expect("proof");
+    let leaves = vec![(h256(5), h256(105))];
+    let root_hex = hex::encode(smt.root().as_slice());
+    assert!(proof
+        .verify_hex::<Blake2bHasher>(&root_hex, leaves.clone())
+        .expect("verify"));
+    assert_eq!(
+        proof.verify_hex::<Blake2bHasher>(&root_hex.replace('0', "x"), leaves.clone()),
+        Err(Error::InvalidHexChar('x'))
+    );
+    assert_eq!(
+        proof.verify_hex::<Blake2bHasher>(&root_hex[2..], leaves),
+        Err(Error::InvalidHexLength(62))
+    );
+}
+
+#[test]
+fn test_proof_does_not_carry_leaf_values() {
+    let mut smt = new_smt((1..=20).map(|i| (h256(i), h256(i + 100))).collect());
+    let proof = smt.merkle_proof(vec![h256(7)]).expect("proof");
+    smt.update(h256(7), h256(200)).expect("update");
+    let proof2 = smt.merkle_proof(vec![h256(7)]).expect("proof");
+    assert_eq!(
+        proof.serialize::<Blake2bHasher>(),
+        proof2.serialize::<Blake2bHasher>()
+    );
+
+    // leaf hashes are computed from the values supplied to verify
+    let root = *smt.root();
+    assert!(proof
+        .clone()
+        .verify::<Blake2bHasher>(&root, vec![(h256(7), h256(200))])
+        .expect("verify"));
+    assert!(!proof
+        .verify::<Blake2bHasher>(&root, vec![(h256(7), h256(107))])
+        .expect("verify"));
+}
+
+#[test]
+fn test_verify_strict() {
+    let smt = new_smt((1..=20).map(|i| (h256(i), h256(i + 100))).collect());
+    let proof = smt.merkle_proof(vec![h256(5)]).expect("proof");
+    assert_eq!(
+        proof.verify_strict::<Blake2bHasher>(smt.root(), vec![(h256(5), h256(105))]),
+        Ok(())
+    );
+    assert_eq!(
+        proof.verify_strict::<Blake2bHasher>(smt.root(), vec![(h256(5), h256(106))]),
+        Err(Error::VerificationFailed)
+    );
+    // errors of verify are propagated
+    assert_eq!(
+        proof.verify_strict::<Blake2bHasher>(smt.root(), vec![]),
+        Err(Error::EmptyKeys)
+    );
+}
+
+#[test]
+fn test_verify_borrowed() {
+    let smt = new_smt((1..=20).map(|i| (h256(i), h256(i + 100))).collect());
+    let leaves: Vec<(H256, H256)> = (3..=8).map(|i| (h256(i), h256(i + 100))).collect();
+    let leaves_refs: Vec<&(H256, H256)> = leaves.iter().collect();
+    let proof = smt
+        .merkle_proof(leaves.iter().map(|(k, _v)| *k).collect())
+        .expect("proof");
+    assert!(proof
+        .clone()
+        .verify_borrowed::<Blake2bHasher>(smt.root(), leaves_refs.iter().copied())
+        .expect("verify"));
+    assert!(proof
+        .clone()
+        .verify_borrowed::<Blake2bHasher>(smt.root(), &leaves[..])
+        .expect("verify"));
+    assert_eq!(
+        proof.verify_borrowed::<Blake2bHasher>(smt.root(), &leaves[1..]),
+        Err(Error::IncorrectNumberOfLeaves {
+            expected: 6,
+            actual: 5
+        })
+    );
+}
+
+#[test]
+fn test_verify_consistency() {
+    let mut smt = new_smt((1..=10).map(|i| (h256(i), h256(i + 100))).collect());
+    let old_root = *smt.root();
+    let old_leaves: Vec<(H256, H256)> = (1..=10).map(|i| (h256(i), h256(i + 100))).collect();
+    let old_keys: Vec<H256> = old_leaves.iter().map(|(k, _v)| *k).collect();
+    let old_proof = smt.merkle_proof(old_keys.clone()).expect("proof");
+
+    // append new entries
+    for i in 11..=20 {
+        smt.update(h256(i), h256(i + 100)).expect("update");
+    }
+    let new_root = *smt.root();
+    let proof = smt.consistency_proof(old_keys.clone()).expect("proof");
+    assert!(proof
+        .verify_consistency::<Blake2bHasher>(
+            old_proof.clone(),
+            &old_root,
+            &new_root,
+            old_leaves.clone()
+        )
+        .expect("verify"));
+
+    // an old entry is changed
+    smt.update(h256(3), h256(200)).expect("update");
+    let changed_root = *smt.root();
+    let proof = smt.consistency_proof(old_keys.clone()).expect("proof");
+    assert!(!proof
+        .verify_consistency::<Blake2bHasher>(old_proof, &old_root, &changed_root, old_leaves)
+        .expect("verify"));
+
+    // an old entry is removed
+    smt.update(h256(4), H256::zero()).expect("update");
+    assert_eq!(
+        smt.consistency_proof(old_keys).err(),
+        Some(Error::NonExistentKey(h256(4)))
+    );
+}
+
+#[test]
+fn test_verify_from_bytes() {
+    let smt = new_smt((1..=20).map(|i| (h256(i), h256(i + 100))).collect());
+    let leaves: Vec<(H256, H256)> = (15..=25)
+        .map(|i| (h256(i), smt.get(&h256(i)).unwrap()))
+        .collect();
+    let proof = smt
+        .merkle_proof(leaves.iter().map(|(k, _v)| *k).collect())
+        .expect("proof");
+    let bytes = proof.serialize::<Blake2bHasher>();
+
+    for leaves in [
+        leaves.clone(),
+        vec![(h256(15), h256(1))]
+            .into_iter()
+            .chain(leaves[1..].iter().cloned())
+            .collect(),
+    ]
+    .iter()
+    {
+        let expected = MerkleProof::deserialize::<Blake2bHasher>(&bytes)
+            .expect("deserialize")
+            .verify::<Blake2bHasher>(smt.root(), leaves.clone());
+        assert_eq!(
+            MerkleProof::verify_from_bytes::<Blake2bHasher>(&bytes, smt.root(), leaves.clone()),
+            expected
+        );
+    }
+    assert_eq!(
+        MerkleProof::verify_from_bytes::<Blake2bHasher>(&bytes, smt.root(), leaves.clone()),
+        Ok(true)
+    );
+
+    let truncated = &bytes[..bytes.len() - 1];
+    assert_eq!(
+        MerkleProof::verify_from_bytes::<Blake2bHasher>(truncated, smt.root(), leaves.clone()),
+        Err(Error::TruncatedProof {
+            expected: bytes.len(),
+            actual: bytes.len() - 1
+        })
+    );
+    assert_eq!(
+        MerkleProof::verify_from_bytes::<Blake2bHasher>(&bytes[..3], smt.root(), leaves),
+        Err(Error::TruncatedProof {
+            expected: 6,
+            actual: 3
+        })
+    );
+}
+
+#[test]
+fn test_hasher_mismatch() {
+    // a hasher with the id of SHA256
+    #[derive(Default)]
+    struct Sha256Hasher(Blake2bHasher);
+    impl Hasher for Sha256Hasher {
+        const ID: u8 = 2;
+        fn write_h256(&mut self, h: &H256) {
+            self.0.write_h256(h);
+        }
+        fn finish(self) -> H256 {
+            self.0.finish()
+        }
+    }
+
+    let smt = new_smt((1..=20).map(|i| (h256(i), h256(i + 100))).collect());
+    let proof = smt.merkle_proof(vec![h256(5)]).expect("proof");
+    let leaves = vec![(h256(5), h256(105))];
+    let bytes = proof.serialize::<Blake2bHasher>();
+    assert_eq!(bytes[1], 1);
+    let mismatch = Err(Error::HasherMismatch {
+        expected: 2,
+        actual: 1,
+    });
+    assert_eq!(
+        MerkleProof::deserialize::<Sha256Hasher>(&bytes).map(|_| ()),
+        mismatch
+    );
+    assert_eq!(
+        MerkleProof::verify_from_bytes::<Sha256Hasher>(&bytes, smt.root(), leaves.clone())
+            .map(|_| ()),
+        mismatch
+    );
+    let mut verifier = ProofVerifier::new();
+    verifier.feed(&bytes).expect("feed");
+    assert_eq!(
+        verifier
+            .finalize::<Sha256Hasher>(smt.root(), leaves.clone())
+            .map(|_| ()),
+        mismatch
+    );
+    assert_eq!(
+        MerkleProof::verify_from_bytes::<Blake2bHasher>(&bytes, smt.root(), leaves),
+        Ok(true)
+    );
+}
+
+#[test]
+fn test_extract_single() {
+    let smt = new_smt((1..=20).map(|i| (h256(i), h256(i + 100))).collect());
+    // 25 is absent
+    let leaves: Vec<(H256, H256)> = [3, 4, 25]
+        .iter()
+        .map(|i| (h256(*i), smt.get(&h256(*i)).unwrap()))
+        .collect();
+    let proof = smt
+        .merkle_proof(leaves.iter().map(|(k, _v)| *k).collect())
+        .expect("proof");
+    for (key, value) in &leaves {
+        let single_proof = proof
+            .extract_single::<Blake2bHasher>(*key, &leaves)
+            .expect("extract");
+        assert_eq!(single_proof, smt.merkle_proof(vec![*key]).expect("proof"));
+        assert!(single_proof
+            .verify::<Blake2bHasher>(smt.root(), vec![(*key, *value)])
+            .expect("verify"));
+    }
+    assert_eq!(
+        proof.extract_single::<Blake2bHasher>(h256(5), &leaves),
+        Err(Error::NonExistentKey(h256(5)))
+    );
+
+    // tree only contains one leaf
+    let smt = new_smt(vec![(h256(1), h256(101))]);
+    let leaves = vec![(h256(1), h256(101)), (h256(2), H256::zero())];
+    let proof = smt.merkle_proof(vec![h256(1), h256(2)]).expect("proof");
+    let single_proof = proof
+        .extract_single::<Blake2bHasher>(h256(1), &leaves)
+        .expect("extract");
+    assert_eq!(
+        single_proof,
+        smt.merkle_proof(vec![h256(1)]).expect("proof")
+    );
+}
+
+// hash the data of verify_with_hash_fn by Blake2bHasher, the data is a pair of H256
+fn blake2b_hash(data: &[u8]) -> H256 {
+    let mut hasher = Blake2bHasher::default();
+    for chunk in data.chunks(32) {
+        let mut buf = [0u8; 32];
+        buf.copy_from_slice(chunk);
+        hasher.write_h256(&buf.into());
+    }
+    hasher.finish()
+}
+
+#[test]
+fn test_verify_with_hash_fn() {
+    let smt = new_smt((1..=20).map(|i| (h256(i), h256(i + 100))).collect());
+    let leaves: Vec<(H256, H256)> = (15..=25)
+        .map(|i| (h256(i), smt.get(&h256(i)).unwrap()))
+        .collect();
+    let proof = smt
+        .merkle_proof(leaves.iter().map(|(k, _v)| *k).collect())
+        .expect("proof");
+    let mut hash_count = 0;
+    let hash = |data: &[u8]| {
+        hash_count += 1;
+        blake2b_hash(data)
+    };
+    assert!(proof
+        .verify_with_hash_fn(smt.root(), leaves.clone(), hash)
+        .expect("verify"));
+    assert!(hash_count > 0);
+
+    let mut wrong_leaves = leaves.clone();
+    wrong_leaves[0].1 = h256(200);
+    for leaves in [leaves, wrong_leaves].iter() {
+        let expected = proof
+            .clone()
+            .verify::<Blake2bHasher>(smt.root(), leaves.clone());
+        let hash = |data: &[u8]| blake2b_hash(data);
+        assert_eq!(
+            proof.verify_with_hash_fn(smt.root(), leaves.clone(), hash),
+            expected
+        );
+    }
+}
+
+#[test]
+fn test_verify_and_compile() {
+    let mut smt = new_smt((1..=20).map(|i| (h256(i), h256(i + 100))).collect());
+    let leaves: Vec<(H256, H256)> = (3..=8).map(|i| (h256(i), h256(i + 100))).collect();
+    let proof = smt
+        .merkle_proof(leaves.iter().map(|(k, _v)| *k).collect())
+        .expect("proof");
+    let (valid, compiled_proof) = proof
+        .clone()
+        .verify_and_compile::<Blake2bHasher>(smt.root(), leaves.clone())
+        .expect("verify");
+    assert!(valid);
+    assert!(compiled_proof
+        .verify::<Blake2bHasher>(smt.root(), leaves.clone())
+        .expect("verify"));
+
+    // re-verify against a new root, other leaves are updated
+    smt.update(h256(15), h256(200)).expect("update");
+    let new_proof = smt
+        .merkle_proof(leaves.iter().map(|(k, _v)| *k).collect())
+        .expect("proof");
+    let new_compiled_proof = new_proof.compile(leaves.clone()).expect("compile");
+    assert!(new_compiled_proof
+        .verify::<Blake2bHasher>(smt.root(), leaves.clone())
+        .expect("verify"));
+    assert!(!compiled_proof
+        .verify::<Blake2bHasher>(smt.root(), leaves.clone())
+        .expect("verify"));
+
+    let (valid, _compiled_proof) = proof
+        .verify_and_compile::<Blake2bHasher>(smt.root(), leaves)
+        .expect("verify");
+    assert!(!valid);
+}
+
+#[test]
+fn test_proof_version() {
+    let smt = new_smt((1..=20).map(|i| (h256(i), h256(i + 100))).collect());
+    let proof = smt.merkle_proof(vec![h256(5), h256(6)]).expect("proof");
+    let leaves = vec![(h256(5), h256(105)), (h256(6), h256(106))];
+    let bytes = proof.serialize::<Blake2bHasher>();
+    assert_eq!(bytes[0], PROOF_VERSION);
+
+    assert_eq!(
+        MerkleProof::deserialize::<Blake2bHasher>(&bytes),
+        Ok(proof.clone())
+    );
+
+    // relabelling a proof as an old version doesn't skip the hasher check
+    let mut v1_bytes = bytes.clone();
+    v1_bytes[0] = 1;
+    assert_eq!(
+        MerkleProof::deserialize::<Blake2bHasher>(&v1_bytes),
+        Err(Error::UnsupportedProofVersion(1))
+    );
+    assert_eq!(
+        MerkleProof::verify_from_bytes::<Blake2bHasher>(&v1_bytes, smt.root(), leaves.clone()),
+        Err(Error::UnsupportedProofVersion(1))
+    );
+
+    let mut v99_bytes = bytes;
+    v99_bytes[0] = 99;
+    assert_eq!(
+        MerkleProof::deserialize::<Blake2bHasher>(&v99_bytes),
+        Err(Error::UnsupportedProofVersion(99))
+    );
+    assert_eq!(
+        MerkleProof::verify_from_bytes::<Blake2bHasher>(&v99_bytes, smt.root(), leaves),
+        Err(Error::UnsupportedProofVersion(99))
+    );
+    let mut verifier = ProofVerifier::new();
+    assert_eq!(
+        verifier.feed(&v99_bytes[..1]),
+        Err(Error::UnsupportedProofVersion(99))
+    );
+}
+
+#[test]
+fn test_proof_golden() {
+    let smt = new_smt(vec![
+        (h256(1), h256(101)),
+        (h256(2), h256(102)),
+        (h256(3), h256(103)),
+    ]);
+    // key 4 is absent
+    let proof = smt
+        .merkle_proof(vec![h256(1), h256(3), h256(4)])
+        .expect("proof");
+    // version | hasher id | leaves count | paths | proof count | sibling of key 2 at height 248
+    let expected = "02\
+                    01\
+                    03000000\
+                    0200f9fa\
+                    0200f8f9\
+                    0100fa\
+                    01000000\
+                    303623d01aadc29692546465e621b6c8c2537ec1850fc8ee41b811fb39ee9a71f8";
+    assert_eq!(hex::encode(proof.serialize::<Blake2bHasher>()), expected);
+    assert_eq!(
+        hex::encode(smt.root().as_slice()),
+        "d65d75beee749084d119f6efdaaddf0881dfd843e0473e81585eb05ac813944a"
+    );
+    assert!(proof
+        .verify::<Blake2bHasher>(
+            smt.root(),
+            vec![
+                (h256(1), h256(101)),
+                (h256(3), h256(103)),
+                (h256(4), H256::zero())
+            ]
+        )
+        .expect("verify"));
+}
+
+#[test]
+fn test_verify_zero_root() {
+    let proof = MerkleProof::new(Vec::new(), Vec::new());
+    let absent = vec![(h256(1), H256::zero()), (h256(2), H256::zero())];
+    assert!(proof
+        .clone()
+        .verify::<Blake2bHasher>(&H256::zero(), absent)
+        .expect("verify"));
+    let present = vec![(h256(1), H256::zero()), (h256(2), h256(102))];
+    assert!(!proof
+        .verify::<Blake2bHasher>(&H256::zero(), present)
+        .expect("verify"));
+
+    // the proof of absence from an empty tree also verifies
+    let smt = new_smt(Vec::new());
+    let proof = smt.merkle_proof(vec![h256(1)]).expect("gen proof");
+    assert!(proof
+        .verify::<Blake2bHasher>(smt.root(), vec![(h256(1), H256::zero())])
+        .expect("verify"));
+}
+
+#[test]
+fn test_verify_zero_root_entry_points() {
+    use crate::proof_pool::ProofPool;
+
+    let smt = new_smt(Vec::new());
+    let root = H256::zero();
+    let keys = vec![h256(1), h256(2)];
+    let proof = smt.merkle_proof(keys.clone()).expect("gen proof");
+    let bytes = proof.serialize::<Blake2bHasher>();
+    let absent: Vec<_> = keys.iter().map(|k| (*k, H256::zero())).collect();
+    let present = vec![(h256(1), H256::zero()), (h256(2), h256(102))];
+    let pool = ProofPool::new(1);
+    let mut pooled = pool.get();
+    pooled
+        .deserialize::<Blake2bHasher>(&bytes)
+        .expect("deserialize");
+
+    for (leaves, expected) in [(absent, true), (present, false)] {
+        assert_eq!(
+            proof.clone().verify::<Blake2bHasher>(&root, leaves.clone()),
+            Ok(expected)
+        );
+        assert_eq!(
+            MerkleProof::verify_from_bytes::<Blake2bHasher>(&bytes, &root, leaves.clone()),
+            Ok(expected)
+        );
+        let verifier = {
+            let mut verifier = ProofVerifier::new();
+            verifier.feed(&bytes).expect("feed");
+            verifier
+        };
+        assert_eq!(
+            verifier.finalize::<Blake2bHasher>(&root, leaves.clone()),
+            Ok(expected)
+        );
+        assert_eq!(
+            proof
+                .clone()
+                .verify_or_root::<Blake2bHasher>(&root, leaves.clone())
+                .expect("verify")
+                .is_ok(),
+            expected
+        );
+        assert_eq!(
+            proof.verify_with_hash_fn(&root, leaves.clone(), blake2b_hash),
+            Ok(expected)
+        );
+        let compiled = proof.clone().compile(leaves.clone()).expect("compile");
+        assert_eq!(
+            compiled.verify::<Blake2bHasher>(&root, leaves.clone()),
+            Ok(expected)
+        );
+        assert_eq!(pooled.verify::<Blake2bHasher>(&root, &leaves), Ok(expected));
+        let changes = leaves.iter().map(|(k, v)| (*k, *v, H256::zero())).collect();
+        assert_eq!(
+            proof.verify_transition::<Blake2bHasher>(&root, &root, changes),
+            Ok(expected)
+        );
+    }
+}
+
+#[test]
+fn test_verify_with_summary() {
+    let smt = new_smt((1..=3).map(|i| (h256(i), h256(100 + i))).collect());
+    let keys = vec![h256(1), h256(2), h256(4), h256(5), h256(6)];
+    let leaves: Vec<_> = keys
+        .iter()
+        .map(|k| (*k, smt.get(k).expect("get")))
+        .collect();
+    let proof = smt.merkle_proof(keys).expect("gen proof");
+    let summary = proof
+        .verify_with_summary::<Blake2bHasher>(smt.root(), leaves.clone())
+        .expect("verify");
+    assert_eq!(
+        summary,
+        VerifySummary {
+            matched: true,
+            present: 2,
+            absent: 3
+        }
+    );
+    let summary = proof
+        .verify_with_summary::<Blake2bHasher>(&h256(42), leaves)
+        .expect("verify");
+    assert!(!summary.matched);
+}
+
+#[test]
+fn test_circuit_friendly_proof() {
+    use crate::merge::{hash_leaf, merge_branch};
+
+    // the fold a circuit author would write
+    fn reference_fold(key: &H256, value: &H256, siblings: &[H256], directions: &[bool]) -> H256 {
+        let mut node = hash_leaf::<Blake2bHasher>(key, value);
+        for height in 0..=255u8 {
+            let sibling = &siblings[height as usize];
+            let parent_path = key.parent_path(height);
+            node = if directions[height as usize] {
+                merge_branch::<Blake2bHasher>(height, &parent_path, sibling, &node)
+            } else {
+                merge_branch::<Blake2bHasher>(height, &parent_path, &node, sibling)
+            };
+        }
+        node
+    }
+
+    let smt = new_smt((1..=20).map(|i| (h256(i), h256(100 + i))).collect());
+    for key in [h256(1), h256(7), h256(42)] {
+        let value = smt.get(&key).expect("get");
+        let proof = smt.circuit_friendly_proof(key).expect("proof");
+        assert_eq!(proof.siblings.len(), 256);
+        assert_eq!(
+            &reference_fold(&key, &value, &proof.siblings, &proof.directions()),
+            smt.root()
+        );
+        assert!(proof.verify::<Blake2bHasher>(smt.root(), &value));
+        assert!(!proof.verify::<Blake2bHasher>(smt.root(), &h256(99)));
+    }
+}
+
+#[test]
+fn test_prove_absent() {
+    let smt = new_smt(
+        [10, 20, 30]
+            .iter()
+            .map(|i| (h256(*i), h256(100 + i)))
+            .collect(),
+    );
+    let proof = smt.prove_absent(h256(15)).expect("prove");
+    assert_eq!(proof.left, Some((h256(10), h256(110))));
+    assert_eq!(proof.right, Some((h256(20), h256(120))));
+    assert!(proof
+        .verify_absent::<Blake2bHasher>(smt.root())
+        .expect("verify"));
+
+    // neighbors must surround the key
+    let mut swapped = proof.clone();
+    core::mem::swap(&mut swapped.left, &mut swapped.right);
+    assert!(!swapped
+        .verify_absent::<Blake2bHasher>(smt.root())
+        .expect("verify"));
+
+    // no leaf at the left side
+    let proof = smt.prove_absent(h256(5)).expect("prove");
+    assert_eq!(proof.left, None);
+    assert_eq!(proof.right, Some((h256(10), h256(110))));
+    assert!(proof
+        .verify_absent::<Blake2bHasher>(smt.root())
+        .expect("verify"));
+
+    assert_eq!(
+        smt.prove_absent(h256(20)),
+        Err(Error::ExistentKey(h256(20)))
+    );
+
+    // the neighbors must be adjacent, 20 is between 10 and 30
+    let wide = AbsenceProof {
+        key: h256(15),
+        left: Some((h256(10), h256(110))),
+        right: Some((h256(30), h256(130))),
+        proof: smt
+            .merkle_proof(vec![h256(10), h256(15), h256(30)])
+            .expect("gen proof"),
+    };
+    assert!(!wide
+        .verify_absent::<Blake2bHasher>(smt.root())
+        .expect("verify"));
+    let no_left = AbsenceProof {
+        key: h256(25),
+        left: None,
+        right: Some((h256(30), h256(130))),
+        proof: smt
+            .merkle_proof(vec![h256(25), h256(30)])
+            .expect("gen proof"),
+    };
+    assert!(!no_left
+        .verify_absent::<Blake2bHasher>(smt.root())
+        .expect("verify"));
+}
+
+#[test]
+fn test_prove_absent_random() {
+    use rand::Rng;
+
+    let mut rng = rand::thread_rng();
+    let mut pairs: Vec<(H256, H256)> = (0..100)
+        .map(|_| (rng.gen::<[u8; 32]>().into(), rng.gen::<[u8; 32]>().into()))
+        .collect();
+    let smt = new_smt(pairs.clone());
+    pairs.sort_unstable_by_key(|(k, _v)| *k);
+    for _ in 0..20 {
+        let key: H256 = rng.gen::<[u8; 32]>().into();
+        let proof = smt.prove_absent(key).expect("prove");
+        let i = pairs.partition_point(|(k, _v)| k < &key);
+        assert_eq!(proof.left, i.checked_sub(1).map(|i| pairs[i]));
+        assert_eq!(proof.right, pairs.get(i).copied());
+        assert!(proof
+            .verify_absent::<Blake2bHasher>(smt.root())
+            .expect("verify"));
+    }
+}
+
+#[test]
+fn test_proof_text_encodings() {
+    let smt = new_smt((1..=10).map(|i| (h256(i), h256(100 + i))).collect());
+    let proof = smt
+        .merkle_proof(vec![h256(1), h256(5), h256(42)])
+        .expect("gen proof");
+    let hex = proof.to_hex::<Blake2bHasher>();
+    assert_eq!(hex, hex::encode(proof.serialize::<Blake2bHasher>()));
+    assert_eq!(
+        MerkleProof::from_hex::<Blake2bHasher>(&hex),
+        Ok(proof.clone())
+    );
+    let base64 = proof.to_base64::<Blake2bHasher>();
+    assert_eq!(
+        MerkleProof::from_base64::<Blake2bHasher>(&base64),
+        Ok(proof.clone())
+    );
+
+    assert_eq!(
+        MerkleProof::from_hex::<Blake2bHasher>(&hex[1..]),
+        Err(Error::OddHexLength(hex.len() - 1))
+    );
+    assert_eq!(
+        MerkleProof::from_hex::<Blake2bHasher>("0g"),
+        Err(Error::InvalidHexChar('g'))
+    );
+    for invalid in [&base64[1..], "AA=A", "A*==", "AB==", "===="] {
+        assert_eq!(
+            MerkleProof::from_base64::<Blake2bHasher>(invalid),
+            Err(Error::InvalidBase64),
+            "{}",
+            invalid
+        );
+    }
+}
+
+#[test]
+fn test_base64_vectors() {
+    use crate::encoding::{base64_decode, base64_encode};
+    // RFC 4648 test vectors
+    for (bytes, encoded) in [
+        ("", ""),
+        ("f", "Zg=="),
+        ("fo", "Zm8="),
+        ("foo", "Zm9v"),
+        ("foob", "Zm9vYg=="),
+        ("fooba", "Zm9vYmE="),
+        ("foobar", "Zm9vYmFy"),
+    ] {
+        assert_eq!(base64_encode(bytes.as_bytes()), encoded);
+        assert_eq!(base64_decode(encoded).expect("decode"), bytes.as_bytes());
+    }
+}
+
+#[test]
+fn test_proof_state() {
+    use crate::proof_state::ProofState;
+
+    let mut smt = new_smt((1..=10).map(|i| (h256(i), h256(100 + i))).collect());
+    let keys = vec![h256(2), h256(5), h256(42)];
+    let (proof, leaves) = smt.merkle_proof_with_values(keys).expect("gen proof");
+    let mut state: ProofState<Blake2bHasher> =
+        ProofState::new(proof.clone(), *smt.root(), leaves.clone()).expect("state");
+    assert!(ProofState::<Blake2bHasher>::new(proof, h256(1), leaves).is_err());
+
+    for (key, value) in [
+        (h256(5), h256(55)),
+        (h256(42), h256(142)),
+        (h256(2), H256::zero()),
+    ] {
+        let root = *state.set(key, value).expect("set");
+        smt.update(key, value).expect("update");
+        assert_eq!(&root, smt.root());
+        assert_eq!(state.get(&key), Ok(value));
+    }
+    assert_eq!(
+        state.set(h256(3), h256(33)),
+        Err(Error::NonExistentKey(h256(3)))
+    );
+}
+
+#[cfg(debug_assertions)]
+#[test]
+fn test_verify_trace() {
+    let smt = new_smt((1..=10).map(|i| (h256(i), h256(100 + i))).collect());
+    let (proof, leaves) = smt
+        .merkle_proof_with_values(vec![h256(2), h256(5), h256(42)])
+        .expect("gen proof");
+    let (matched, trace) = proof
+        .verify_trace::<Blake2bHasher>(smt.root(), &leaves)
+        .expect("trace");
+    assert!(matched);
+    assert_eq!(trace.last().map(|(_height, node)| node), Some(smt.root()));
+    assert!(trace[..3].iter().all(|(height, _node)| *height == 0));
+    assert!(trace[3..].windows(2).all(|pair| pair[0].0 <= pair[1].0));
+
+    // a wrong leaf diverges from its leaf hash
+    let mut wrong_leaves = leaves.clone();
+    wrong_leaves[1].1 = h256(200);
+    let (matched, wrong_trace) = proof
+        .verify_trace::<Blake2bHasher>(smt.root(), &wrong_leaves)
+        .expect("trace");
+    assert!(!matched);
+    assert_eq!(wrong_trace.len(), trace.len());
+    let diverged = trace.iter().zip(&wrong_trace).position(|(a, b)| a != b);
+    assert_eq!(diverged, Some(1));
+
+    // a single leaf tree doesn't merge
+    let smt = new_smt(vec![(h256(1), h256(101))]);
+    let (proof, leaves) = smt
+        .merkle_proof_with_values(vec![h256(1)])
+        .expect("gen proof");
+    let (matched, trace) = proof
+        .verify_trace::<Blake2bHasher>(smt.root(), &leaves)
+        .expect("trace");
+    assert!(matched);
+    assert_eq!(trace, vec![(0, *smt.root())]);
+}
+
+#[test]
+fn test_proof_pool() {
+    use crate::proof_pool::ProofPool;
+
+    let smt = new_smt((1..=50).map(|i| (h256(i), h256(100 + i))).collect());
+    let pool = ProofPool::new(2);
+    for i in 1..=60u8 {
+        // proofs of different sizes reuse the buffers
+        let keys: Vec<H256> = (i..=i + i % 5).map(h256).collect();
+        let (proof, leaves) = smt.merkle_proof_with_values(keys).expect("gen proof");
+        let bytes = proof.serialize::<Blake2bHasher>();
+        let mut pooled = pool.get();
+        pooled
+            .deserialize::<Blake2bHasher>(&bytes)
+            .expect("deserialize");
+        assert_eq!(*pooled, proof);
+        assert!(pooled
+            .verify::<Blake2bHasher>(smt.root(), &leaves)
+            .expect("verify"));
+        assert!(!pooled
+            .verify::<Blake2bHasher>(&h256(1), &leaves)
+            .expect("verify"));
+        assert_eq!(
+            pooled.deserialize::<Blake2bHasher>(&bytes[..bytes.len() - 1]),
+            Err(Error::CorruptedProof)
+        );
+        // the partially parsed proof is not kept
+        assert_eq!(pooled.leaves_count(), 0);
+        assert!(pooled.proof().is_empty());
+        assert!(pooled.verify::<Blake2bHasher>(smt.root(), &leaves).is_err());
+    }
+    assert_eq!(pool.free_count(), 1);
+    // a recycled buffer doesn't deref to the previous proof
+    let bytes = smt
+        .merkle_proof(vec![h256(1)])
+        .expect("gen proof")
+        .serialize::<Blake2bHasher>();
+    let mut pooled = pool.get();
+    pooled
+        .deserialize::<Blake2bHasher>(&bytes)
+        .expect("deserialize");
+    drop(pooled);
+    assert_eq!(pool.get().leaves_count(), 0);
+    let (a, b, c) = (pool.get(), pool.get(), pool.get());
+    drop((a, b, c));
+    assert_eq!(pool.free_count(), 2);
+}
+
+#[test]
+fn test_verify_partial() {
+    let smt = new_smt((1..=10).map(|i| (h256(i), h256(100 + i))).collect());
+    let all_keys = vec![h256(2), h256(5), h256(42)];
+    let proof = smt.partial_proof(all_keys.clone()).expect("gen proof");
+
+    // the value of key 5 is unknown
+    let known_leaves = vec![(h256(2), h256(102)), (h256(42), H256::zero())];
+    assert!(proof
+        .verify_partial::<Blake2bHasher>(smt.root(), known_leaves, all_keys.clone())
+        .expect("verify"));
+    assert!(proof
+        .verify_partial::<Blake2bHasher>(smt.root(), vec![(h256(5), h256(105))], all_keys.clone())
+        .expect("verify"));
+    assert!(!proof
+        .verify_partial::<Blake2bHasher>(smt.root(), vec![(h256(2), h256(200))], all_keys.clone())
+        .expect("verify"));
+    assert!(!proof
+        .verify_partial::<Blake2bHasher>(smt.root(), vec![(h256(42), h256(142))], all_keys.clone())
+        .expect("verify"));
+
+    assert_eq!(
+        proof.verify_partial::<Blake2bHasher>(smt.root(), vec![(h256(3), h256(103))], all_keys),
+        Err(Error::NonExistentKey(h256(3)))
+    );
+    assert_eq!(
+        proof.verify_partial::<Blake2bHasher>(smt.root(), Vec::new(), vec![h256(2)]),
+        Err(Error::IncorrectNumberOfLeaves {
+            expected: 3,
+            actual: 1
+        })
+    );
+}
+
+// verify_single_no_alloc reimplements the verification, it must agree with MerkleProof::verify,
+// both Ok with a same result or both Err, the kind of parse errors can differ
+fn assert_same_verify(bytes: &[u8], root: &H256, key: &H256, value: &H256) {
+    let expected = MerkleProof::deserialize::<Blake2bHasher>(bytes)
+        .and_then(|proof| proof.verify::<Blake2bHasher>(root, vec![(*key, *value)]));
+    let actual = verify_single_no_alloc::<Blake2bHasher>(
+        bytes,
+        &(*root).into(),
+        &(*key).into(),
+        &(*value).into(),
+    );
+    match (&expected, &actual) {
+        (Ok(expected), Ok(actual)) => assert_eq!(expected, actual, "proof {:?}", bytes),
+        (Err(_), Err(_)) => {}
+        _ => panic!(
+            "verify {:?}, no_alloc verify {:?}, proof {:?}",
+            expected, actual, bytes
+        ),
+    }
+}
+
+proptest::proptest! {
+    #[test]
+    fn test_verify_single_no_alloc_differential(
+        pairs in proptest::collection::vec(proptest::prelude::any::<([u8; 32], [u8; 32])>(), 1..20),
+        absent: Option<[u8; 32]>,
+        pick: usize,
+        path_edits: Vec<(usize, u8)>,
+        height_edits: Vec<(usize, u8)>,
+        drop_item: Option<usize>,
+        extra_item: Option<([u8; 32], u8)>,
+        byte_edit: Option<(usize, u8)>,
+        truncate: Option<usize>,
+    ) {
+        let pairs: Vec<(H256, H256)> = pairs.into_iter().map(|(k, v)| (k.into(), v.into())).collect();
+        let smt = new_smt(pairs.clone());
+        let key = absent.map(H256::from).unwrap_or(pairs[pick % pairs.len()].0);
+        let value = smt.get(&key).expect("get");
+        let proof = smt.merkle_proof(vec![key]).expect("proof");
+        assert_same_verify(&proof.serialize::<Blake2bHasher>(), smt.root(), &key, &value);
+
+        // malformed paths and proof items
+        let mut leaves_path = proof.leaves_path().clone();
+        let mut items = proof.proof().clone();
+        for (i, height) in path_edits.into_iter().take(4) {
+            let path = &mut leaves_path[0];
+            if path.is_empty() || i % 3 == 0 {
+                path.insert(i % (path.len() + 1), height);
+            } else {
+                let len = path.len();
+                path[i % len] = height;
+            }
+        }
+        for (i, height) in height_edits.into_iter().take(4) {
+            if !items.is_empty() {
+                let len = items.len();
+                items[i % len].1 = height;
+            }
+        }
+        if let Some(i) = drop_item {
+            if !items.is_empty() {
+                items.remove(i % items.len());
+            }
+        }
+        if let Some((node, height)) = extra_item {
+            items.push((node.into(), height));
+        }
+        let malformed = MerkleProof::new(leaves_path, items);
+        let mut bytes = malformed.serialize::<Blake2bHasher>();
+        if let Some((i, byte)) = byte_edit {
+            let len = bytes.len();
+            bytes[i % len] = byte;
+        }
+        if let Some(len) = truncate {
+            bytes.truncate(len % (bytes.len() + 1));
+        }
+        assert_same_verify(&bytes, smt.root(), &key, &value);
+        assert_same_verify(&bytes, &H256::zero(), &key, &value);
+        // a root computed from the malformed proof, both verifiers must accept it
+        let computed_root = MerkleProof::deserialize::<Blake2bHasher>(&bytes)
+            .and_then(|proof| proof.compute_root::<Blake2bHasher>(vec![(key, value)]));
+        if let Ok(root) = computed_root {
+            assert_same_verify(&bytes, &root, &key, &value);
+        }
+    }
+}
@@ -1,2 +1,20 @@
+#![allow(
+    clippy::upper_case_acronyms,
+    clippy::clone_on_copy,
+    clippy::empty_line_after_doc_comments,
+    clippy::legacy_numeric_constants,
+    clippy::needless_borrow,
+    clippy::useless_conversion
+)]
+
+mod allocation;
+mod edge_cases;
 mod fixtures;
+mod fn_hasher;
+mod h256;
+mod merkle_proof;
+mod node_codec;
+mod proof_cache;
+#[cfg(feature = "tracing")]
+mod trace;
 mod tree;
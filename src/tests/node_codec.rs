@@ -0,0 +1,103 @@
+use crate::*;
+use crate::{
+    blake2b::Blake2bHasher,
+    default_store::DefaultStore,
+    node_codec::{CodecHasher, NodeCodec},
+    traits::Hasher,
+    tree::root_of_sorted,
+};
+use rand::prelude::Rng;
+
+/// Prepend a version tag, and commit to the position of the branch
+struct VersionCodec;
+
+impl NodeCodec for VersionCodec {
+    fn write_branch<H: Hasher + ?Sized>(
+        hasher: &mut H,
+        height: u8,
+        node_key: &H256,
+        lhs: &H256,
+        rhs: &H256,
+    ) {
+        let mut tag = [0u8; 32];
+        tag[0] = 1;
+        tag[1] = height;
+        hasher.write_h256(&tag.into());
+        hasher.write_h256(node_key);
+        hasher.write_h256(lhs);
+        hasher.write_h256(rhs);
+    }
+}
+
+type VersionHasher = CodecHasher<Blake2bHasher, VersionCodec>;
+type VersionSMT = SparseMerkleTree<VersionHasher, H256, DefaultStore<H256>>;
+
+#[test]
+fn test_custom_codec() {
+    let mut rng = rand::thread_rng();
+    let mut pairs: Vec<(H256, H256)> = (0..50)
+        .map(|_| (rng.gen::<[u8; 32]>().into(), rng.gen::<[u8; 32]>().into()))
+        .collect();
+    let mut tree = VersionSMT::default();
+    let mut default_tree: SparseMerkleTree<Blake2bHasher, H256, DefaultStore<H256>> =
+        Default::default();
+    for (key, value) in &pairs {
+        tree.update(*key, *value).expect("update");
+        default_tree.update(*key, *value).expect("update");
+    }
+    assert_ne!(tree.root(), default_tree.root());
+
+    // same root is computed from sorted pairs
+    pairs.sort_unstable_by_key(|(k, _v)| *k);
+    assert_eq!(
+        &root_of_sorted::<VersionHasher>(pairs.clone().into_iter()).expect("root"),
+        tree.root()
+    );
+
+    let leaves: Vec<(H256, H256)> = pairs.iter().step_by(7).cloned().collect();
+    let proof = tree
+        .merkle_proof(leaves.iter().map(|(k, _v)| *k).collect())
+        .expect("proof");
+    assert!(proof
+        .clone()
+        .verify::<VersionHasher>(tree.root(), leaves.clone())
+        .expect("verify"));
+    assert!(!proof
+        .clone()
+        .verify::<Blake2bHasher>(tree.root(), leaves.clone())
+        .expect("verify"));
+    let compiled_proof = proof.compile(leaves.clone()).expect("compile");
+    assert!(compiled_proof
+        .verify::<VersionHasher>(tree.root(), leaves)
+        .expect("verify"));
+
+    // removing leaves
+    tree.set_lazy_delete(true);
+    for (key, _value) in pairs.iter().skip(1).step_by(3) {
+        tree.remove(*key).expect("remove");
+    }
+    tree.compact().expect("compact");
+    let remain: Vec<(H256, H256)> = tree.leaves().collect::<Result<_, _>>().expect("leaves");
+    assert_eq!(
+        &root_of_sorted::<VersionHasher>(remain.into_iter()).expect("root"),
+        tree.root()
+    );
+}
+
+#[test]
+fn test_merge_equals_default_codec() {
+    use crate::merge::{merge, merge_branch};
+
+    let lhs: H256 = [1u8; 32].into();
+    let rhs: H256 = [2u8; 32].into();
+    let node_key: H256 = [3u8; 32].into();
+    assert_eq!(
+        merge::<Blake2bHasher>(&lhs, &rhs),
+        merge_branch::<Blake2bHasher>(7, &node_key, &lhs, &rhs)
+    );
+    assert_ne!(
+        merge::<VersionHasher>(&lhs, &rhs),
+        merge_branch::<VersionHasher>(7, &node_key, &lhs, &rhs)
+    );
+    assert_eq!(merge::<Blake2bHasher>(&H256::zero(), &rhs), rhs);
+}
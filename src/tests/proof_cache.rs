@@ -0,0 +1,101 @@
+use crate::*;
+use crate::{
+    blake2b::Blake2bHasher, default_store::DefaultStore, proof_cache::ProofCache, SparseMerkleTree,
+};
+use std::{thread, time::Duration};
+
+type SMT = SparseMerkleTree<Blake2bHasher, H256, DefaultStore<H256>>;
+
+fn new_smt(n: u8) -> SMT {
+    let mut smt = SMT::default();
+    for i in 1..=n {
+        smt.update([i; 32].into(), [i; 32].into()).unwrap();
+    }
+    smt
+}
+
+#[test]
+fn test_proof_cache_root_keyed() {
+    let mut smt = new_smt(10);
+    let mut cache = ProofCache::default();
+    let keys: Vec<H256> = vec![[1u8; 32].into(), [2u8; 32].into()];
+    let proof = cache.merkle_proof(&smt, keys.clone()).expect("proof");
+    assert_eq!(cache.len(), 1);
+    // order of keys doesn't matter
+    let reversed: Vec<H256> = keys.iter().rev().cloned().collect();
+    assert_eq!(cache.get(smt.root(), &reversed), Some(proof));
+
+    // proofs of other roots are not returned
+    smt.update([3u8; 32].into(), H256::zero()).unwrap();
+    assert_eq!(cache.get(smt.root(), &keys), None);
+    let proof = cache.merkle_proof(&smt, keys.clone()).expect("proof");
+    assert_eq!(cache.len(), 2);
+    let leaves = keys.into_iter().map(|k| (k, k)).collect();
+    assert!(proof
+        .verify::<Blake2bHasher>(smt.root(), leaves)
+        .expect("verify"));
+}
+
+#[test]
+fn test_proof_cache_ttl() {
+    let smt = new_smt(10);
+    let mut cache = ProofCache::with_ttl(Duration::from_millis(50));
+    let keys: Vec<H256> = vec![[1u8; 32].into()];
+    let proof = cache.merkle_proof(&smt, keys.clone()).expect("proof");
+    assert_eq!(cache.get(smt.root(), &keys), Some(proof.clone()));
+
+    thread::sleep(Duration::from_millis(60));
+    // expired entry is recomputed
+    assert_eq!(cache.get(smt.root(), &keys), None);
+    assert!(cache.is_empty());
+    assert_eq!(cache.merkle_proof(&smt, keys.clone()), Ok(proof));
+    assert_eq!(cache.len(), 1);
+}
+
+#[test]
+fn test_proof_cache_mock_clock() {
+    use crate::proof_cache::Clock;
+    use std::{cell::Cell, rc::Rc, time::Instant};
+
+    struct MockClock {
+        start: Instant,
+        elapsed: Rc<Cell<Duration>>,
+    }
+    impl Clock for MockClock {
+        fn now(&self) -> Instant {
+            self.start + self.elapsed.get()
+        }
+    }
+
+    let smt = new_smt(10);
+    let elapsed = Rc::new(Cell::new(Duration::from_secs(0)));
+    let clock = MockClock {
+        start: Instant::now(),
+        elapsed: Rc::clone(&elapsed),
+    };
+    let mut cache = ProofCache::with_clock(16, Some(Duration::from_secs(60)), clock);
+    let keys: Vec<H256> = vec![[1u8; 32].into()];
+    let proof = cache.merkle_proof(&smt, keys.clone()).expect("proof");
+
+    elapsed.set(Duration::from_secs(59));
+    assert_eq!(cache.get(smt.root(), &keys), Some(proof));
+    elapsed.set(Duration::from_secs(60));
+    assert_eq!(cache.get(smt.root(), &keys), None);
+    assert!(cache.is_empty());
+}
+
+#[test]
+fn test_proof_cache_lru() {
+    let smt = new_smt(10);
+    let mut cache = ProofCache::new(2);
+    let key = |i: u8| -> Vec<H256> { vec![[i; 32].into()] };
+    cache.merkle_proof(&smt, key(1)).expect("proof");
+    cache.merkle_proof(&smt, key(2)).expect("proof");
+    // use key 1, then key 2 becomes the least recently used one
+    assert!(cache.get(smt.root(), &key(1)).is_some());
+    cache.merkle_proof(&smt, key(3)).expect("proof");
+    assert_eq!(cache.len(), 2);
+    assert!(cache.get(smt.root(), &key(1)).is_some());
+    assert!(cache.get(smt.root(), &key(2)).is_none());
+    assert!(cache.get(smt.root(), &key(3)).is_some());
+}
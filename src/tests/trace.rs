@@ -0,0 +1,65 @@
+use crate::*;
+use crate::{blake2b::Blake2bHasher, default_store::DefaultStore, SparseMerkleTree};
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, Mutex,
+};
+use tracing::{span, Event, Metadata, Subscriber};
+
+type SMT = SparseMerkleTree<Blake2bHasher, H256, DefaultStore<H256>>;
+
+/// Records names of the created spans and the fields of events
+#[derive(Default)]
+struct Recorder {
+    spans: Mutex<Vec<&'static str>>,
+    events: Mutex<Vec<String>>,
+    next_id: AtomicU64,
+}
+
+struct FieldsVisitor<'a>(&'a mut String);
+
+impl tracing::field::Visit for FieldsVisitor<'_> {
+    fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn core::fmt::Debug) {
+        self.0.push_str(&format!("{}={:?} ", field.name(), value));
+    }
+}
+
+struct Shared(Arc<Recorder>);
+
+impl Subscriber for Shared {
+    fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+        true
+    }
+    fn new_span(&self, span: &span::Attributes<'_>) -> span::Id {
+        self.0.spans.lock().unwrap().push(span.metadata().name());
+        span::Id::from_u64(self.0.next_id.fetch_add(1, Ordering::SeqCst) + 1)
+    }
+    fn record(&self, _span: &span::Id, _values: &span::Record<'_>) {}
+    fn record_follows_from(&self, _span: &span::Id, _follows: &span::Id) {}
+    fn event(&self, event: &Event<'_>) {
+        let mut fields = String::new();
+        event.record(&mut FieldsVisitor(&mut fields));
+        self.0.events.lock().unwrap().push(fields);
+    }
+    fn enter(&self, _span: &span::Id) {}
+    fn exit(&self, _span: &span::Id) {}
+}
+
+#[test]
+fn test_trace_spans() {
+    let recorder = Arc::new(Recorder::default());
+    let key: H256 = [1u8; 32].into();
+    let value: H256 = [2u8; 32].into();
+    tracing::subscriber::with_default(Shared(Arc::clone(&recorder)), || {
+        let mut smt = SMT::default();
+        smt.update(key, value).expect("update");
+        let proof = smt.merkle_proof(vec![key]).expect("proof");
+        assert!(proof
+            .verify::<Blake2bHasher>(smt.root(), vec![(key, value)])
+            .expect("verify"));
+    });
+    let spans = recorder.spans.lock().unwrap();
+    assert_eq!(*spans, vec!["update", "merkle_proof", "verify"]);
+    let events = recorder.events.lock().unwrap();
+    assert_eq!(*events, vec!["leaves=1 proof_size=0 ".to_string()]);
+}
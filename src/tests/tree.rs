@@ -28,6 +28,524 @@ fn test_default_root() {
     assert_eq!(tree.get(&H256::zero()).expect("get"), H256::zero());
 }
 
+#[test]
+fn test_update_with_tombstoned() {
+    let key: H256 = [7u8; 32].into();
+    let mut tree = SMT::default();
+    tree.update([1u8; 32].into(), [1u8; 32].into())
+        .expect("update");
+    tree.update(key, [2u8; 32].into()).expect("update");
+    tree.set_lazy_delete(true);
+    tree.remove(key).expect("remove");
+    tree.update_with(key, |value| {
+        assert_eq!(value, H256::zero());
+        [3u8; 32].into()
+    })
+    .expect("update_with");
+    assert_eq!(tree.get(&key).expect("get"), [3u8; 32].into());
+}
+
+#[test]
+fn test_update_with_counter() {
+    fn increment(value: H256) -> H256 {
+        let mut buf: [u8; 32] = value.into();
+        buf[0] += 1;
+        buf.into()
+    }
+    let key: H256 = [7u8; 32].into();
+    let mut tree = SMT::default();
+    tree.update([1u8; 32].into(), [1u8; 32].into())
+        .expect("update");
+    for _ in 0..5 {
+        tree.update_with(key, increment).expect("update_with");
+    }
+    let mut expected = [0u8; 32];
+    expected[0] = 5;
+    assert_eq!(tree.get(&key).expect("get"), expected.into());
+    assert_eq!(tree.store().leaves_map().len(), 2);
+
+    let mut expected_tree = SMT::default();
+    expected_tree
+        .update([1u8; 32].into(), [1u8; 32].into())
+        .expect("update");
+    expected_tree.update(key, expected.into()).expect("update");
+    assert_eq!(tree.root(), expected_tree.root());
+
+    // return zero to delete the key
+    tree.update_with(key, |_| H256::zero())
+        .expect("update_with");
+    assert_eq!(tree.get(&key).expect("get"), H256::zero());
+    assert_eq!(tree.store().leaves_map().len(), 1);
+}
+
+#[test]
+fn test_send_sync() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<H256>();
+    assert_send_sync::<SMT>();
+    assert_send_sync::<DefaultStore<H256>>();
+    assert_send_sync::<MerkleProof>();
+    assert_send_sync::<CompiledMerkleProof>();
+    assert_send_sync::<crate::error::Error>();
+    assert_send_sync::<crate::proof_cache::ProofCache>();
+}
+
+#[test]
+fn test_update_invalid_value() {
+    // odd numbers are invalid
+    #[derive(Debug, Clone, Default, PartialEq)]
+    struct EvenValue(u64);
+    impl crate::traits::Value for EvenValue {
+        fn to_h256(&self) -> H256 {
+            let mut buf = [0u8; 32];
+            buf[..8].copy_from_slice(&self.0.to_le_bytes());
+            buf.into()
+        }
+        fn zero() -> Self {
+            EvenValue(0)
+        }
+        fn validate(&self) -> bool {
+            self.0.is_multiple_of(2)
+        }
+    }
+    let mut tree: SparseMerkleTree<Blake2bHasher, EvenValue, DefaultStore<EvenValue>> =
+        SparseMerkleTree::default();
+    let key: H256 = [1u8; 32].into();
+    tree.update(key, EvenValue(2)).expect("update");
+    let root = *tree.root();
+    assert_eq!(
+        tree.update(key, EvenValue(3)),
+        Err(Error::InvalidValue(key))
+    );
+    // the tree is untouched
+    assert_eq!(tree.root(), &root);
+    assert_eq!(tree.get(&key).expect("get"), EvenValue(2));
+    let other: H256 = [2u8; 32].into();
+    assert_eq!(
+        tree.update_all(vec![(other, EvenValue(4)), (key, EvenValue(5))]),
+        Err(Error::InvalidValue(key))
+    );
+    assert_eq!(tree.root(), &root);
+    assert_eq!(tree.get(&other).expect("get"), EvenValue(0));
+}
+
+#[test]
+fn test_diff_delta_sync() {
+    let mut rng = rand::thread_rng();
+    let pairs: Vec<(H256, H256)> = (0..50)
+        .map(|_| (rng.gen::<[u8; 32]>().into(), rng.gen::<[u8; 32]>().into()))
+        .collect();
+    // the replica shares some leaves, has stale values and extra leaves
+    let mine = new_smt(pairs[..40].to_vec());
+    let mut replica_pairs = pairs[10..].to_vec();
+    for (_key, value) in replica_pairs[..10].iter_mut() {
+        *value = rng.gen::<[u8; 32]>().into();
+    }
+    let mut replica = new_smt(replica_pairs);
+
+    let delta = mine.diff_delta(&replica).expect("diff");
+    // 10 missing, 10 stale, 10 extra
+    assert_eq!(delta.updates.len(), 30);
+    let bytes = delta.serialize();
+    let delta = crate::tree::TreeDelta::deserialize(&bytes).expect("deserialize");
+    replica.apply_delta(&delta).expect("apply");
+    assert_eq!(replica.root(), mine.root());
+    assert!(mine.diff_delta(&replica).expect("diff").updates.is_empty());
+
+    assert_eq!(
+        crate::tree::TreeDelta::deserialize(&bytes[..bytes.len() - 1]),
+        Err(Error::InvalidDeltaLength(bytes.len() - 1))
+    );
+}
+
+#[test]
+fn test_update_all_affecting() {
+    let mut rng = rand::thread_rng();
+    let pairs: Vec<(H256, H256)> = (0..8)
+        .map(|_| (rng.gen::<[u8; 32]>().into(), rng.gen::<[u8; 32]>().into()))
+        .collect();
+    let mut smt = new_smt(pairs.clone());
+    let absent: H256 = rng.gen::<[u8; 32]>().into();
+    let watched = vec![pairs[0].0, pairs[1].0, pairs[2].0, absent];
+    let old_proofs: Vec<_> = watched
+        .iter()
+        .map(|k| smt.merkle_proof(vec![*k]).expect("proof"))
+        .collect();
+
+    // rewriting the current value changes nothing
+    let (root, stale) = smt
+        .update_all_affecting(vec![pairs[1]], &watched)
+        .expect("update");
+    assert_eq!(&root, smt.root());
+    assert!(stale.is_empty());
+
+    // the proof of the changed key keeps its siblings
+    let (root, stale) = smt
+        .update_all_affecting(
+            vec![(pairs[0].0, H256::from([1u8; 32])), pairs[1]],
+            &watched,
+        )
+        .expect("update");
+    assert_eq!(&root, smt.root());
+    assert_eq!(stale, watched[1..].to_vec());
+    for (key, old_proof) in watched.iter().zip(old_proofs) {
+        let proof = smt.merkle_proof(vec![*key]).expect("proof");
+        assert_eq!(stale.contains(key), proof != old_proof);
+    }
+
+    assert_eq!(
+        smt.update_all_affecting(vec![pairs[2], pairs[2]], &watched),
+        Err(Error::DuplicateKey(pairs[2].0))
+    );
+}
+
+#[test]
+fn test_deterministic_store_layout() {
+    fn serialize_store(smt: &SMT) -> Vec<u8> {
+        let store = smt.store();
+        let mut branches: Vec<_> = store.branches_map().iter().collect();
+        branches.sort_by_key(|(node, _branch)| **node);
+        let mut leaves: Vec<_> = store.leaves_map().iter().collect();
+        leaves.sort_by_key(|(leaf_hash, _leaf)| **leaf_hash);
+        let mut buf = Vec::new();
+        for (node, branch) in branches {
+            assert_eq!(branch_node_id::<Blake2bHasher>(branch), *node);
+            buf.extend_from_slice(node.as_slice());
+            buf.push(branch.fork_height);
+            buf.extend_from_slice(branch.key.as_slice());
+            match branch.node_type {
+                crate::tree::NodeType::Pair(left, right) => {
+                    buf.extend_from_slice(left.as_slice());
+                    buf.extend_from_slice(right.as_slice());
+                }
+                crate::tree::NodeType::Single(leaf_hash) => {
+                    buf.extend_from_slice(leaf_hash.as_slice());
+                }
+            }
+        }
+        for (leaf_hash, leaf) in leaves {
+            buf.extend_from_slice(leaf_hash.as_slice());
+            buf.extend_from_slice(leaf.key.as_slice());
+            buf.extend_from_slice(leaf.value.as_slice());
+        }
+        buf
+    }
+
+    let mut rng = rand::thread_rng();
+    let mut pairs: Vec<(H256, H256)> = (0..50)
+        .map(|_| (rng.gen::<[u8; 32]>().into(), rng.gen::<[u8; 32]>().into()))
+        .collect();
+    let smt1 = new_smt(pairs.clone());
+    pairs.shuffle(&mut rng);
+    let mut smt2 = new_smt(pairs.clone());
+    // insert and delete an extra key
+    let extra: H256 = rng.gen::<[u8; 32]>().into();
+    smt2.update(extra, [1u8; 32].into()).expect("update");
+    smt2.update(extra, H256::zero()).expect("update");
+    assert_eq!(smt1.root(), smt2.root());
+    assert_eq!(serialize_store(&smt1), serialize_store(&smt2));
+}
+
+#[test]
+fn test_max_proof_keys() {
+    let keys = |n: u8| -> Vec<H256> { (1..=n).map(|i| [i; 32].into()).collect() };
+    // a small tree of 3 leaves
+    let mut smt = new_smt(keys(3).into_iter().map(|k| (k, k)).collect());
+    assert!(smt.merkle_proof(keys(7)).is_ok());
+    smt.set_max_proof_keys(Some(2)).expect("set");
+    assert!(smt.merkle_proof(keys(6)).is_ok());
+    assert_eq!(
+        smt.merkle_proof(keys(7)),
+        Err(Error::TooManyKeys { max: 6, actual: 7 })
+    );
+    // the limit follows updates
+    smt.update([4u8; 32].into(), [4u8; 32].into())
+        .expect("update");
+    assert!(smt.merkle_proof(keys(8)).is_ok());
+    smt.set_lazy_delete(true);
+    smt.remove([1u8; 32].into()).expect("remove");
+    smt.remove([2u8; 32].into()).expect("remove");
+    smt.compact().expect("compact");
+    assert_eq!(
+        smt.merkle_proof(keys(5)),
+        Err(Error::TooManyKeys { max: 4, actual: 5 })
+    );
+    smt.set_max_proof_keys(None).expect("set");
+    assert!(smt.merkle_proof(keys(7)).is_ok());
+
+    // a large tree of 200 leaves
+    let mut smt = new_smt(keys(200).into_iter().map(|k| (k, k)).collect());
+    smt.set_max_proof_keys(Some(1)).expect("set");
+    assert!(smt.merkle_proof(keys(200)).is_ok());
+    let mut too_many = keys(200);
+    too_many.push([201u8; 32].into());
+    assert_eq!(
+        smt.merkle_proof(too_many),
+        Err(Error::TooManyKeys {
+            max: 200,
+            actual: 201
+        })
+    );
+
+    // an empty tree allows factor keys
+    let mut smt = new_smt(Vec::new());
+    smt.set_max_proof_keys(Some(2)).expect("set");
+    assert!(smt.merkle_proof(keys(2)).is_ok());
+    assert!(smt.merkle_proof(keys(3)).is_err());
+}
+
+#[test]
+fn test_snapshot_shared_store() {
+    use std::sync::Arc;
+
+    let pairs: Vec<(H256, H256)> = (1..=3u8)
+        .map(|i| ([i; 32].into(), [i; 32].into()))
+        .collect();
+    let mut smt: SparseMerkleTree<Blake2bHasher, H256, Arc<DefaultStore<H256>>> =
+        SparseMerkleTree::default();
+    smt.update_all(pairs.clone()).expect("update");
+    let root = *smt.root();
+    let snapshot = smt.snapshot();
+    // the store is shared until the next write
+    assert_eq!(Arc::strong_count(smt.store()), 2);
+
+    smt.update([1u8; 32].into(), [42u8; 32].into())
+        .expect("update");
+    assert_eq!(Arc::strong_count(smt.store()), 1);
+    assert_eq!(snapshot.root(), &root);
+    for (key, value) in &pairs {
+        assert_eq!(&snapshot.get(key).expect("get"), value);
+    }
+    assert_eq!(
+        smt.root(),
+        new_smt(vec![
+            pairs[1],
+            pairs[2],
+            ([1u8; 32].into(), [42u8; 32].into())
+        ])
+        .root()
+    );
+}
+
+#[test]
+fn test_snapshot() {
+    let pairs: Vec<(H256, H256)> = (1..=3u8)
+        .map(|i| ([i; 32].into(), [i; 32].into()))
+        .collect();
+    let mut smt = new_smt(pairs.clone());
+    let root = *smt.root();
+    let snapshot = smt.snapshot();
+    let shared = snapshot.clone();
+
+    smt.update([1u8; 32].into(), [42u8; 32].into())
+        .expect("update");
+    smt.update([2u8; 32].into(), H256::zero()).expect("update");
+    smt.update([4u8; 32].into(), [4u8; 32].into())
+        .expect("update");
+    assert_ne!(smt.root(), &root);
+
+    for snapshot in [snapshot, shared] {
+        assert_eq!(snapshot.root(), &root);
+        for (key, value) in &pairs {
+            assert_eq!(&snapshot.get(key).expect("get"), value);
+        }
+        assert_eq!(snapshot.get(&[4u8; 32].into()).expect("get"), H256::zero());
+        let proof = snapshot
+            .merkle_proof(pairs.iter().map(|(k, _v)| *k).collect())
+            .expect("proof");
+        assert!(proof
+            .verify::<Blake2bHasher>(&root, pairs.clone())
+            .expect("verify"));
+    }
+}
+
+#[test]
+fn test_combine_shard_roots() {
+    use crate::tree::combine_shard_roots;
+
+    let mut rng = rand::thread_rng();
+    let pairs: Vec<(H256, H256)> = (0..50)
+        .map(|_| (rng.gen::<[u8; 32]>().into(), rng.gen::<[u8; 32]>().into()))
+        .collect();
+    let smt = new_smt(pairs.clone());
+    // shard by the top bit
+    let (right, left): (Vec<_>, Vec<_>) = pairs.into_iter().partition(|(k, _v)| k.get_bit(255));
+    let mut right_prefix = H256::zero();
+    right_prefix.set_bit(255);
+    let roots = [
+        (right_prefix, 1, *new_smt(right).root()),
+        (H256::zero(), 1, *new_smt(left).root()),
+    ];
+    assert_eq!(
+        combine_shard_roots::<Blake2bHasher>(&roots).expect("combine"),
+        *smt.root()
+    );
+    // a single shard owns all keys
+    assert_eq!(
+        combine_shard_roots::<Blake2bHasher>(&[(H256::zero(), 0, *smt.root())]).expect("combine"),
+        *smt.root()
+    );
+    assert_eq!(
+        combine_shard_roots::<Blake2bHasher>(&[roots[0], (H256::zero(), 0, *smt.root())]),
+        Err(Error::NonMergableRange)
+    );
+}
+
+#[test]
+fn test_verify_leaf_hashes() {
+    let pairs: Vec<(H256, H256)> = (1..=3u8)
+        .map(|i| ([i; 32].into(), [i; 32].into()))
+        .collect();
+    let mut smt = new_smt(pairs);
+    smt.verify_leaf_hashes().expect("verify");
+    let key: H256 = [2u8; 32].into();
+    let leaf_hash = hash_leaf::<Blake2bHasher>(&key, &key);
+    crate::traits::Store::insert_leaf(
+        smt.store_mut(),
+        leaf_hash,
+        crate::tree::LeafNode {
+            key,
+            value: [42u8; 32].into(),
+        },
+    )
+    .expect("insert");
+    assert_eq!(smt.verify_leaf_hashes(), Err(Error::CorruptedLeaf { key }));
+}
+
+#[test]
+fn test_get_ref_no_clone() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    static CLONES: AtomicUsize = AtomicUsize::new(0);
+
+    #[derive(Debug, Default, PartialEq)]
+    struct LargeValue(Vec<u8>);
+    impl Clone for LargeValue {
+        fn clone(&self) -> Self {
+            CLONES.fetch_add(1, Ordering::SeqCst);
+            LargeValue(self.0.clone())
+        }
+    }
+    impl crate::traits::Value for LargeValue {
+        fn to_h256(&self) -> H256 {
+            if self.0.is_empty() {
+                return H256::zero();
+            }
+            let mut buf = [0u8; 32];
+            let mut hasher = blake2b_rs::Blake2bBuilder::new(32).personal(b"SMT").build();
+            hasher.update(&self.0);
+            hasher.finalize(&mut buf);
+            buf.into()
+        }
+        fn zero() -> Self {
+            LargeValue::default()
+        }
+    }
+
+    let mut tree: SparseMerkleTree<Blake2bHasher, LargeValue, DefaultStore<LargeValue>> =
+        SparseMerkleTree::default();
+    let key: H256 = [1u8; 32].into();
+    tree.update(key, LargeValue(vec![7u8; 4096]))
+        .expect("update");
+    let clones = CLONES.load(Ordering::SeqCst);
+    let value = tree.get_ref(&key).expect("get").expect("exists");
+    assert_eq!(value.0.len(), 4096);
+    assert_eq!(tree.get_ref(&[2u8; 32].into()).expect("get"), None);
+    assert_eq!(CLONES.load(Ordering::SeqCst), clones);
+}
+
+#[test]
+fn test_get_ref_unsupported_store() {
+    use crate::{
+        traits::Store,
+        tree::{BranchNode, LeafNode},
+    };
+
+    // a store which doesn't lend references
+    #[derive(Default)]
+    struct CloningStore(DefaultStore<H256>);
+    impl Store<H256> for CloningStore {
+        fn get_branch(&self, node: &H256) -> Result<Option<BranchNode>, Error> {
+            self.0.get_branch(node)
+        }
+        fn get_leaf(&self, leaf_hash: &H256) -> Result<Option<LeafNode<H256>>, Error> {
+            self.0.get_leaf(leaf_hash)
+        }
+        fn insert_branch(&mut self, node: H256, branch: BranchNode) -> Result<(), Error> {
+            self.0.insert_branch(node, branch)
+        }
+        fn insert_leaf(&mut self, leaf_hash: H256, leaf: LeafNode<H256>) -> Result<(), Error> {
+            self.0.insert_leaf(leaf_hash, leaf)
+        }
+        fn remove_branch(&mut self, node: &H256) -> Result<(), Error> {
+            self.0.remove_branch(node)
+        }
+        fn remove_leaf(&mut self, leaf_hash: &H256) -> Result<(), Error> {
+            self.0.remove_leaf(leaf_hash)
+        }
+    }
+
+    let mut tree: SparseMerkleTree<Blake2bHasher, H256, CloningStore> = SparseMerkleTree::default();
+    let key: H256 = [1u8; 32].into();
+    tree.update(key, [7u8; 32].into()).expect("update");
+    tree.update([2u8; 32].into(), [8u8; 32].into())
+        .expect("update");
+    assert_eq!(tree.get_ref(&key), Err(Error::RefUnsupported));
+    assert_eq!(tree.get(&key), Ok([7u8; 32].into()));
+}
+
+#[test]
+fn test_per_key_proof_strategy() {
+    let mut rng = rand::thread_rng();
+    for leaves_count in [0, 1, 2, 50] {
+        let pairs: Vec<(H256, H256)> = (0..leaves_count)
+            .map(|_| (rng.gen::<[u8; 32]>().into(), rng.gen::<[u8; 32]>().into()))
+            .collect();
+        let smt = new_smt(pairs.clone());
+        let absent: Vec<H256> = (0..5).map(|_| rng.gen::<[u8; 32]>().into()).collect();
+        let mut keys: Vec<H256> = pairs.iter().map(|(k, _v)| *k).chain(absent).collect();
+        // keys sharing long prefixes with the first key
+        for height in [0, 1, 7, 200, 255] {
+            let mut key = keys[0];
+            if key.get_bit(height) {
+                key.clear_bit(height);
+            } else {
+                key.set_bit(height);
+            }
+            keys.push(key);
+        }
+        for count in [1, 2, 3, 8, keys.len()] {
+            let mut keys: Vec<H256> = keys.iter().take(count).copied().collect();
+            let proof = smt.merkle_proof(keys.clone()).expect("proof");
+            assert_eq!(proof, smt.multi_keys_proof(keys.clone()).expect("proof"));
+            keys.sort_unstable();
+            assert_eq!(proof, smt.per_key_proof(&keys).expect("proof"));
+            let leaves: Vec<(H256, H256)> = keys
+                .iter()
+                .map(|k| (*k, smt.get(k).expect("get")))
+                .collect();
+            assert!(proof
+                .verify::<Blake2bHasher>(smt.root(), leaves)
+                .expect("verify"));
+        }
+    }
+}
+
+#[test]
+fn test_hash_leaf() {
+    let key: H256 = [1u8; 32].into();
+    let value: H256 = [42u8; 32].into();
+    let mut tree = SMT::default();
+    tree.update(key, value).expect("update");
+    let hash = hash_leaf::<Blake2bHasher>(&key, &value);
+    assert!(tree.store().leaves_map().contains_key(&hash));
+    // a single leaf tree's root is the leaf hash
+    assert_eq!(tree.root(), &hash);
+    assert_eq!(
+        hash_leaf::<Blake2bHasher>(&key, &H256::zero()),
+        H256::zero()
+    );
+}
+
 #[test]
 fn test_default_tree() {
     let tree = SMT::default();
@@ -374,6 +892,34 @@ proptest! {
         }
     }
 
+    #[test]
+    fn test_fork_height_bitwise(key: [u8; 32], key2: [u8; 32], flip: u8) {
+        let key: H256 = key.into();
+        let mut flipped = key;
+        if key.get_bit(flip) {
+            flipped.clear_bit(flip);
+        } else {
+            flipped.set_bit(flip);
+        }
+        for k2 in [key2.into(), flipped, key] {
+            let expected = (0..=u8::MAX).rev().find(|h| key.get_bit(*h) != k2.get_bit(*h)).unwrap_or(0);
+            assert_eq!(key.fork_height(&k2), expected);
+        }
+    }
+
+    #[test]
+    fn test_get_unchanged((pairs, _n) in leaves(1, 50), absent: Vec<[u8; 32]>) {
+        let smt = new_smt(pairs.clone());
+        let pairs: std::collections::BTreeMap<H256, H256> = pairs.into_iter().collect();
+        for (k, v) in &pairs {
+            assert_eq!(&smt.get(k).expect("get"), v);
+        }
+        for k in absent.into_iter().map(H256::from) {
+            let expected = pairs.get(&k).cloned().unwrap_or_else(H256::zero);
+            assert_eq!(smt.get(&k).expect("get"), expected);
+        }
+    }
+
     #[test]
     fn test_random_update(key: [u8; 32], value: [u8;32]) {
         test_update(key.into(), value.into());
@@ -560,6 +1106,578 @@ proptest! {
     }
 }
 
+#[test]
+fn test_update_all() {
+    let pairs: Vec<(H256, H256)> = (1u8..=10)
+        .map(|i| ([i; 32].into(), [i; 32].into()))
+        .collect();
+    let mut tree = SMT::default();
+    tree.update_all(pairs.clone()).expect("update all");
+    assert_eq!(tree.root(), new_smt(pairs.clone()).root());
+
+    // duplicate keys are rejected without updating
+    let root = *tree.root();
+    let dup_key: H256 = [3u8; 32].into();
+    let batch = vec![
+        (dup_key, [42u8; 32].into()),
+        ([20u8; 32].into(), [20u8; 32].into()),
+        (dup_key, [43u8; 32].into()),
+    ];
+    assert_eq!(
+        tree.update_all(batch.clone()),
+        Err(Error::DuplicateKey(dup_key))
+    );
+    assert_eq!(tree.root(), &root);
+
+    // the last value wins
+    tree.update_all_last_wins(batch).expect("update all");
+    assert_eq!(tree.get(&dup_key), Ok([43u8; 32].into()));
+    assert_eq!(tree.get(&[20u8; 32].into()), Ok([20u8; 32].into()));
+}
+
+#[test]
+fn test_root_of_sorted() {
+    use crate::tree::root_of_sorted;
+
+    assert_eq!(
+        root_of_sorted::<Blake2bHasher>(Vec::new().into_iter()),
+        Ok(H256::zero())
+    );
+    let mut rng = rand::thread_rng();
+    for n in [1, 2, 10, 100].iter() {
+        let mut pairs: Vec<(H256, H256)> = (0..*n)
+            .map(|i| {
+                let value = if i % 7 == 3 {
+                    H256::zero()
+                } else {
+                    rng.gen::<[u8; 32]>().into()
+                };
+                (rng.gen::<[u8; 32]>().into(), value)
+            })
+            .collect();
+        let smt = new_smt(pairs.clone());
+        pairs.sort_unstable_by_key(|(k, _v)| *k);
+        let root = root_of_sorted::<Blake2bHasher>(pairs.clone().into_iter());
+        assert_eq!(root.as_ref(), Ok(smt.root()));
+
+        if pairs.len() > 1 {
+            pairs.swap(0, 1);
+            assert_eq!(
+                root_of_sorted::<Blake2bHasher>(pairs.clone().into_iter()),
+                Err(Error::UnsortedKeys)
+            );
+            pairs[0] = pairs[1];
+            let dup_key = pairs[0].0;
+            assert_eq!(
+                root_of_sorted::<Blake2bHasher>(pairs.into_iter()),
+                Err(Error::DuplicateKey(dup_key))
+            );
+        }
+    }
+}
+
+#[test]
+fn test_replace() {
+    let mut tree = SMT::default();
+    let key: H256 = [1u8; 32].into();
+    let value: H256 = [2u8; 32].into();
+    let value2: H256 = [3u8; 32].into();
+    tree.update(key, value).expect("update");
+    let root = *tree.root();
+
+    assert_eq!(tree.replace(key, value2), Ok(value));
+    assert_eq!(tree.get(&key), Ok(value2));
+    assert_ne!(tree.root(), &root);
+
+    // absent key must not be inserted
+    let root = *tree.root();
+    let absent_key: H256 = [4u8; 32].into();
+    assert_eq!(
+        tree.replace(absent_key, value),
+        Err(Error::NonExistentKey(absent_key))
+    );
+    assert_eq!(tree.get(&absent_key), Ok(H256::zero()));
+    assert_eq!(tree.root(), &root);
+}
+
+#[test]
+fn test_apply_batch_with_proofs() {
+    let mut rng = rand::thread_rng();
+    let mut tree = SMT::default();
+    for _ in 0..20 {
+        let key: [u8; 32] = rng.gen();
+        let value: [u8; 32] = rng.gen();
+        tree.update(key.into(), value.into()).expect("update");
+    }
+    let pairs: Vec<(H256, H256)> = (0..10)
+        .map(|_| (rng.gen::<[u8; 32]>().into(), rng.gen::<[u8; 32]>().into()))
+        .collect();
+    let (root, proofs) = tree
+        .apply_batch_with_proofs(pairs.clone())
+        .expect("apply batch");
+    assert_eq!(&root, tree.root());
+    assert_eq!(proofs.len(), pairs.len());
+    for ((key, value), (proof_key, proof)) in pairs.into_iter().zip(proofs) {
+        assert_eq!(key, proof_key);
+        assert!(proof
+            .verify::<Blake2bHasher>(&root, vec![(key, value)])
+            .expect("verify"));
+    }
+}
+
+#[test]
+fn test_stats() {
+    let mut tree = SMT::default();
+    let empty_stats = tree.stats().expect("stats");
+    assert_eq!(empty_stats.leaves_count, 0);
+    assert_eq!(empty_stats.nodes_count, 0);
+    assert_eq!(empty_stats.estimated_memory, 0);
+
+    // key0 and key1 fork at height 0, key2 forks with them at height 255
+    let key0 = H256::zero();
+    let mut key1 = H256::zero();
+    key1.set_bit(0);
+    let mut key2 = H256::zero();
+    key2.set_bit(255);
+    for key in [key0, key1, key2].iter() {
+        tree.update(*key, [1u8; 32].into()).expect("update");
+    }
+    let stats = tree.stats().expect("stats");
+    assert_eq!(stats.leaves_count, 3);
+    // two forks and three leaves
+    assert_eq!(stats.nodes_count, 5);
+    assert_eq!(stats.max_depth, 2);
+    assert!((stats.average_depth - 5.0 / 3.0).abs() < f64::EPSILON);
+    assert_eq!(
+        stats.estimated_memory,
+        5 * (32 + std::mem::size_of::<tree::BranchNode>())
+            + 3 * (32 + std::mem::size_of::<tree::LeafNode<H256>>())
+    );
+}
+
+#[test]
+fn test_merge_tree() {
+    let mut rng = rand::thread_rng();
+    let pairs: Vec<(H256, H256)> = (0..40)
+        .map(|_| (rng.gen::<[u8; 32]>().into(), rng.gen::<[u8; 32]>().into()))
+        .collect();
+    let mut tree = new_smt(pairs[..20].to_vec());
+    let other = new_smt(pairs[20..].to_vec());
+    tree.merge_tree(other).expect("merge");
+    assert_eq!(tree.root(), new_smt(pairs.clone()).root());
+
+    // collision
+    let root = *tree.root();
+    let (key, _value) = pairs[5];
+    let other = new_smt(vec![
+        (key, [1u8; 32].into()),
+        ([2u8; 32].into(), [2u8; 32].into()),
+    ]);
+    assert_eq!(tree.merge_tree(other), Err(Error::DuplicateKey(key)));
+    assert_eq!(tree.root(), &root);
+
+    // resolve collision
+    let other = new_smt(vec![(key, [1u8; 32].into())]);
+    tree.merge_tree_with(other, |_key, _value, other_value| other_value)
+        .expect("merge");
+    assert_eq!(tree.get(&key), Ok([1u8; 32].into()));
+}
+
+#[test]
+fn test_merkle_proof_with_values() {
+    let mut rng = rand::thread_rng();
+    let pairs: Vec<(H256, H256)> = (0..20)
+        .map(|_| (rng.gen::<[u8; 32]>().into(), rng.gen::<[u8; 32]>().into()))
+        .collect();
+    let smt = new_smt(pairs.clone());
+    let absent_key: H256 = rng.gen::<[u8; 32]>().into();
+    let mut keys: Vec<H256> = pairs[..5].iter().map(|(k, _v)| *k).collect();
+    keys.push(absent_key);
+    let (proof, leaves) = smt.merkle_proof_with_values(keys).expect("proof");
+    assert_eq!(leaves.len(), 6);
+    assert!(leaves.contains(&(absent_key, H256::zero())));
+    for pair in &pairs[..5] {
+        assert!(leaves.contains(pair));
+    }
+    assert!(proof
+        .verify::<Blake2bHasher>(smt.root(), leaves)
+        .expect("verify"));
+}
+
+#[test]
+fn test_contains_all_any() {
+    let tree = new_smt(
+        (1u8..=10)
+            .map(|i| ([i; 32].into(), [i; 32].into()))
+            .collect(),
+    );
+    let present: Vec<H256> = (1u8..=3).map(|i| [i; 32].into()).collect();
+    let absent: Vec<H256> = (11u8..=13).map(|i| [i; 32].into()).collect();
+    let mixed = vec![present[0], absent[0]];
+
+    assert_eq!(tree.contains_all(&present), Ok(true));
+    assert_eq!(tree.contains_all(&mixed), Ok(false));
+    assert_eq!(tree.contains_all(&absent), Ok(false));
+    assert_eq!(tree.contains_any(&present), Ok(true));
+    assert_eq!(tree.contains_any(&mixed), Ok(true));
+    assert_eq!(tree.contains_any(&absent), Ok(false));
+    // empty keys
+    assert_eq!(tree.contains_all(&[]), Ok(true));
+    assert_eq!(tree.contains_any(&[]), Ok(false));
+}
+
+#[test]
+fn test_update_changed() {
+    let mut tree = SMT::default();
+    let key: H256 = [1u8; 32].into();
+    let value: H256 = [2u8; 32].into();
+    assert_eq!(tree.update_changed(key, H256::zero()), Ok(false));
+    assert_eq!(tree.update_changed(key, value), Ok(true));
+    let root = *tree.root();
+    assert_eq!(tree.update_changed(key, value), Ok(false));
+    assert_eq!(tree.root(), &root);
+    assert_eq!(tree.update_changed(key, [3u8; 32].into()), Ok(true));
+    assert_ne!(tree.root(), &root);
+    assert_eq!(tree.update_changed(key, H256::zero()), Ok(true));
+    assert!(tree.is_empty());
+}
+
+#[test]
+fn test_witness_for() {
+    let mut rng = rand::thread_rng();
+    let pairs: Vec<(H256, H256)> = (0..100)
+        .map(|_| (rng.gen::<[u8; 32]>().into(), rng.gen::<[u8; 32]>().into()))
+        .collect();
+    let smt = new_smt(pairs.clone());
+    let absent_key: H256 = rng.gen::<[u8; 32]>().into();
+    let mut hot_keys: Vec<H256> = pairs[..5].iter().map(|(k, _v)| *k).collect();
+    hot_keys.push(absent_key);
+
+    let witness = smt.witness_for(&hot_keys).expect("witness");
+    assert_eq!(witness.root(), smt.root());
+    assert!(witness.store().branches_map().len() < smt.store().branches_map().len());
+    assert_eq!(witness.store().leaves_map().len(), 5);
+    for (key, value) in &pairs[..5] {
+        assert_eq!(witness.get(key), Ok(*value));
+    }
+    assert_eq!(witness.get(&absent_key), Ok(H256::zero()));
+
+    let proof = witness.merkle_proof(hot_keys.clone()).expect("proof");
+    assert_eq!(proof, smt.merkle_proof(hot_keys.clone()).expect("proof"));
+    let leaves = hot_keys.iter().map(|k| (*k, smt.get(k).unwrap())).collect();
+    assert!(proof
+        .verify::<Blake2bHasher>(smt.root(), leaves)
+        .expect("verify"));
+    for key in &hot_keys {
+        assert_eq!(
+            witness.merkle_proof(vec![*key]),
+            smt.merkle_proof(vec![*key])
+        );
+    }
+}
+
+#[test]
+fn test_absence_proof() {
+    let mut rng = rand::thread_rng();
+    let pairs: Vec<(H256, H256)> = (0..100)
+        .map(|_| (rng.gen::<[u8; 32]>().into(), rng.gen::<[u8; 32]>().into()))
+        .collect();
+    let smt = new_smt(pairs.clone());
+    let absent_keys: Vec<H256> = (0..100).map(|_| rng.gen::<[u8; 32]>().into()).collect();
+
+    let proof = smt.absence_proof(absent_keys.clone()).expect("proof");
+    assert!(proof
+        .clone()
+        .verify_absence::<Blake2bHasher>(smt.root(), absent_keys.clone())
+        .expect("verify"));
+    assert_eq!(proof, smt.merkle_proof(absent_keys.clone()).expect("proof"));
+
+    // a present key can't be proved absent
+    let mut keys = absent_keys;
+    keys.push(pairs[0].0);
+    let proof = smt.absence_proof(keys.clone()).expect("proof");
+    assert!(!proof
+        .verify_absence::<Blake2bHasher>(smt.root(), keys)
+        .expect("verify"));
+}
+
+#[test]
+fn test_lazy_delete_compact() {
+    let mut rng = rand::thread_rng();
+    let pairs: Vec<(H256, H256)> = (0..100)
+        .map(|_| (rng.gen::<[u8; 32]>().into(), rng.gen::<[u8; 32]>().into()))
+        .collect();
+    let mut lazy_smt = new_smt(pairs.clone());
+    let mut eager_smt = new_smt(pairs.clone());
+    lazy_smt.set_lazy_delete(true);
+    let root = *lazy_smt.root();
+
+    for (k, _v) in pairs.iter().step_by(3) {
+        lazy_smt.remove(*k).unwrap();
+        eager_smt.remove(*k).unwrap();
+    }
+    // branches are not recomputed before compact
+    assert_eq!(lazy_smt.root(), &root);
+    assert_eq!(lazy_smt.get(&pairs[0].0), Ok(H256::zero()));
+
+    lazy_smt.compact().unwrap();
+    assert_eq!(lazy_smt.root(), eager_smt.root());
+    assert_eq!(
+        lazy_smt.store().leaves_map(),
+        eager_smt.store().leaves_map()
+    );
+    let branches = |smt: &SMT| {
+        smt.store()
+            .branches_map()
+            .iter()
+            .map(|(node, branch)| (*node, branch.fork_height))
+            .collect::<std::collections::BTreeMap<_, _>>()
+    };
+    assert_eq!(branches(&lazy_smt), branches(&eager_smt));
+    for (k, v) in pairs.iter().skip(1).step_by(3) {
+        assert_eq!(lazy_smt.get(k), Ok(*v));
+    }
+
+    // remove all leaves
+    for (k, _v) in &pairs {
+        lazy_smt.remove(*k).unwrap();
+    }
+    lazy_smt.compact().unwrap();
+    assert!(lazy_smt.is_empty());
+    assert_eq!(lazy_smt.store().branches_map().len(), 0);
+    assert_eq!(lazy_smt.store().leaves_map().len(), 0);
+}
+
+// a lazy delete tree with a tombstoned key, and the tree after compact
+fn lazy_smt_with_tombstone() -> (SMT, SMT, H256) {
+    let pairs: Vec<(H256, H256)> = (1..20u8)
+        .map(|i| ([i; 32].into(), [i.wrapping_mul(3); 32].into()))
+        .collect();
+    let mut lazy_smt = new_smt(pairs.clone());
+    lazy_smt.set_lazy_delete(true);
+    let key = pairs[5].0;
+    lazy_smt.remove(key).unwrap();
+    let mut compacted = new_smt(pairs);
+    compacted.remove(key).unwrap();
+    (lazy_smt, compacted, key)
+}
+
+#[test]
+fn test_lazy_delete_merkle_proof_with_values() {
+    let (mut lazy_smt, _compacted, key) = lazy_smt_with_tombstone();
+    let keys = vec![key, [1u8; 32].into()];
+    assert_eq!(
+        lazy_smt.merkle_proof_with_values(keys.clone()).err(),
+        Some(Error::PendingTombstones)
+    );
+    lazy_smt.compact().unwrap();
+    let (proof, leaves) = lazy_smt.merkle_proof_with_values(keys).unwrap();
+    assert!(proof
+        .verify::<Blake2bHasher>(lazy_smt.root(), leaves)
+        .expect("verify"));
+}
+
+#[test]
+fn test_lazy_delete_partial_proof() {
+    let (mut lazy_smt, _compacted, key) = lazy_smt_with_tombstone();
+    let keys = vec![key, [1u8; 32].into()];
+    assert_eq!(
+        lazy_smt.partial_proof(keys.clone()).err(),
+        Some(Error::PendingTombstones)
+    );
+    lazy_smt.compact().unwrap();
+    let proof = lazy_smt.partial_proof(keys.clone()).unwrap();
+    let known = vec![(key, H256::zero())];
+    assert!(proof
+        .verify_partial::<Blake2bHasher>(lazy_smt.root(), known, keys)
+        .expect("verify"));
+}
+
+#[test]
+fn test_lazy_delete_prove_absent() {
+    let (mut lazy_smt, _compacted, key) = lazy_smt_with_tombstone();
+    assert_eq!(
+        lazy_smt.prove_absent(key).err(),
+        Some(Error::PendingTombstones)
+    );
+    assert_eq!(
+        lazy_smt.absence_proof(vec![key]).err(),
+        Some(Error::PendingTombstones)
+    );
+    lazy_smt.compact().unwrap();
+    let proof = lazy_smt.prove_absent(key).unwrap();
+    assert!(proof
+        .verify_absent::<Blake2bHasher>(lazy_smt.root())
+        .expect("verify"));
+}
+
+#[test]
+fn test_lazy_delete_diff_delta() {
+    let (mut lazy_smt, compacted, _key) = lazy_smt_with_tombstone();
+    let mut replica = new_smt(vec![([1u8; 32].into(), [1u8; 32].into())]);
+    assert_eq!(
+        lazy_smt.diff_delta(&replica).err(),
+        Some(Error::PendingTombstones)
+    );
+    assert_eq!(
+        replica.diff_delta(&lazy_smt).err(),
+        Some(Error::PendingTombstones)
+    );
+    lazy_smt.compact().unwrap();
+    assert_eq!(lazy_smt.root(), compacted.root());
+    let delta = lazy_smt.diff_delta(&replica).unwrap();
+    replica.apply_delta(&delta).unwrap();
+    assert_eq!(replica.root(), lazy_smt.root());
+}
+
+#[test]
+fn test_prefix_iter() {
+    let mut rng = rand::thread_rng();
+    let mut pairs: Vec<(H256, H256)> = (0..60)
+        .map(|i| {
+            let mut key: [u8; 32] = rng.gen();
+            // top 8 bits of key
+            key[31] = if i % 2 == 0 { 0xAA } else { 0x55 };
+            (key.into(), rng.gen::<[u8; 32]>().into())
+        })
+        .collect();
+    let smt = new_smt(pairs.clone());
+    pairs.sort_unstable_by_key(|(k, _v)| *k);
+
+    let leaves: Vec<_> = smt.leaves().collect::<Result<_, _>>().unwrap();
+    assert_eq!(leaves, pairs);
+
+    let mut prefix = [0u8; 32];
+    prefix[31] = 0xAA;
+    let matched: Vec<_> = smt
+        .prefix_iter(prefix.into(), 8)
+        .collect::<Result<_, _>>()
+        .unwrap();
+    let expected: Vec<_> = pairs
+        .iter()
+        .filter(|(k, _v)| k.copy_bits(248) == prefix.into())
+        .cloned()
+        .collect();
+    assert_eq!(matched.len(), 30);
+    assert_eq!(matched, expected);
+
+    // 0xAB shares top 7 bits with 0xAA
+    prefix[31] = 0xAB;
+    assert_eq!(smt.prefix_iter(prefix.into(), 8).count(), 0);
+    assert_eq!(smt.prefix_iter(prefix.into(), 7).count(), 30);
+    assert_eq!(smt.prefix_iter(prefix.into(), 0).count(), 60);
+}
+
+#[test]
+fn test_into_iter() {
+    let mut rng = rand::thread_rng();
+    let pairs: std::collections::BTreeMap<H256, H256> = (0..30)
+        .map(|_| (rng.gen::<[u8; 32]>().into(), rng.gen::<[u8; 32]>().into()))
+        .collect();
+    let smt = new_smt(pairs.clone().into_iter().collect());
+    let mut leaves = std::collections::BTreeMap::new();
+    let mut last_key = None;
+    for leaf in &smt {
+        let (key, value) = leaf.expect("leaf");
+        // in order of keys
+        assert!(last_key < Some(key));
+        last_key = Some(key);
+        leaves.insert(key, value);
+    }
+    assert_eq!(leaves, pairs);
+}
+
+#[test]
+fn test_into_iter_by_value() {
+    let mut rng = rand::thread_rng();
+    let pairs: std::collections::BTreeMap<H256, H256> = (0..30)
+        .map(|_| (rng.gen::<[u8; 32]>().into(), rng.gen::<[u8; 32]>().into()))
+        .collect();
+    let smt = new_smt(pairs.clone().into_iter().collect());
+    let drained: Vec<(H256, H256)> = smt.into_iter().collect::<Result<_, _>>().expect("drain");
+    assert_eq!(drained, pairs.into_iter().collect::<Vec<_>>());
+    assert_eq!(SMT::default().into_iter().count(), 0);
+}
+
+#[cfg(feature = "rand")]
+#[test]
+fn test_deterministic() {
+    let tree = SMT::deterministic(50, 42).expect("build");
+    assert_eq!(tree.leaves().count(), 50);
+    assert_eq!(
+        tree.root(),
+        SMT::deterministic(50, 42).expect("build").root()
+    );
+    assert_ne!(
+        tree.root(),
+        SMT::deterministic(50, 43).expect("build").root()
+    );
+    assert_ne!(
+        tree.root(),
+        SMT::deterministic(49, 42).expect("build").root()
+    );
+}
+
+#[cfg(feature = "bumpalo")]
+#[test]
+fn test_bump_store() {
+    use crate::bump_store::BumpStore;
+
+    let mut rng = rand::thread_rng();
+    let pairs: Vec<(H256, H256)> = (0..100)
+        .map(|_| (rng.gen::<[u8; 32]>().into(), rng.gen::<[u8; 32]>().into()))
+        .collect();
+    let bump = bumpalo::Bump::new();
+    let mut tree: SparseMerkleTree<Blake2bHasher, H256, BumpStore<H256>> =
+        SparseMerkleTree::new(H256::zero(), BumpStore::new_in(&bump));
+    tree.update_all(pairs.clone()).expect("update");
+    assert_eq!(tree.root(), new_smt(pairs.clone()).root());
+    for (key, value) in &pairs {
+        assert_eq!(tree.get(key), Ok(*value));
+        assert_eq!(tree.get_ref(key), Ok(Some(value)));
+    }
+    assert!(bump.allocated_bytes() > 0);
+}
+
+#[test]
+fn test_get_range() {
+    let mut rng = rand::thread_rng();
+    let mut pairs: Vec<(H256, H256)> = (0..50)
+        .map(|_| (rng.gen::<[u8; 32]>().into(), rng.gen::<[u8; 32]>().into()))
+        .collect();
+    let smt = new_smt(pairs.clone());
+    pairs.sort_unstable_by_key(|(k, _v)| *k);
+
+    let lo = pairs[10].0;
+    let hi = pairs[30].0;
+    assert_eq!(smt.get_range(lo, hi), Ok(pairs[10..30].to_vec()));
+    // bounds don't need to be keys of the tree
+    let lo = lo.checked_next().unwrap();
+    let hi = hi.checked_next().unwrap();
+    assert_eq!(smt.get_range(lo, hi), Ok(pairs[11..31].to_vec()));
+    assert_eq!(smt.get_range(hi, lo), Ok(Vec::new()));
+    assert_eq!(smt.get_range(lo, lo), Ok(Vec::new()));
+    assert_eq!(
+        smt.get_range(H256::zero(), [255u8; 32].into()),
+        Ok(pairs.clone())
+    );
+}
+
+#[test]
+fn test_keys_values() {
+    let mut rng = rand::thread_rng();
+    let mut pairs: Vec<(H256, H256)> = (0..30)
+        .map(|_| (rng.gen::<[u8; 32]>().into(), rng.gen::<[u8; 32]>().into()))
+        .collect();
+    let smt = new_smt(pairs.clone());
+    pairs.sort_unstable_by_key(|(k, _v)| *k);
+
+    let keys: Vec<H256> = smt.keys().collect::<Result<_, _>>().expect("keys");
+    let values: Vec<H256> = smt.values().collect::<Result<_, _>>().expect("values");
+    assert_eq!(keys, pairs.iter().map(|(k, _v)| *k).collect::<Vec<_>>());
+    assert_eq!(values, pairs.iter().map(|(_k, v)| *v).collect::<Vec<_>>());
+}
+
 #[test]
 fn test_v0_2_broken_sample() {
     fn parse_h256(s: &str) -> H256 {
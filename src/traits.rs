@@ -1,12 +1,24 @@
+#[cfg(feature = "alloc")]
 use crate::{
     error::Error,
     tree::{BranchNode, LeafNode},
+};
+use crate::{
+    node_codec::{DefaultCodec, NodeCodec},
     H256,
 };
 
 /// Trait for customize hash function
 pub trait Hasher {
+    /// Identifier of the hash function, it is recorded in serialized proofs
+    /// 0 for unspecified hash functions
+    const ID: u8 = 0;
     fn write_h256(&mut self, h: &H256);
+    /// Feed a branch to the hasher, the DefaultCodec is used by default
+    /// see node_codec::CodecHasher for customize the encoding
+    fn write_branch(&mut self, height: u8, node_key: &H256, lhs: &H256, rhs: &H256) {
+        DefaultCodec::write_branch(self, height, node_key, lhs, rhs);
+    }
     fn finish(self) -> H256;
 }
 
@@ -14,6 +26,10 @@ pub trait Hasher {
 pub trait Value {
     fn to_h256(&self) -> H256;
     fn zero() -> Self;
+    /// Check the value before it is stored, update returns InvalidValue error for false
+    fn validate(&self) -> bool {
+        true
+    }
 }
 
 impl Value for H256 {
@@ -26,6 +42,7 @@ impl Value for H256 {
 }
 
 /// Trait for customize backend storage
+#[cfg(feature = "alloc")]
 pub trait Store<V> {
     fn get_branch(&self, node: &H256) -> Result<Option<BranchNode>, Error>;
     fn get_leaf(&self, leaf_hash: &H256) -> Result<Option<LeafNode<V>>, Error>;
@@ -33,4 +50,16 @@ pub trait Store<V> {
     fn insert_leaf(&mut self, leaf_hash: H256, leaf: LeafNode<V>) -> Result<(), Error>;
     fn remove_branch(&mut self, node: &H256) -> Result<(), Error>;
     fn remove_leaf(&mut self, leaf_hash: &H256) -> Result<(), Error>;
+    /// Borrow a leaf without cloning it, used by SparseMerkleTree::get_ref
+    /// return None if the leaf doesn't exist,
+    /// return RefUnsupported error if the store can't lend references, which is the default
+    fn leaf_ref(&self, _leaf_hash: &H256) -> Result<Option<&LeafNode<V>>, Error> {
+        Err(Error::RefUnsupported)
+    }
+    /// Remove a leaf and return it, implement it to move the value out without cloning
+    fn take_leaf(&mut self, leaf_hash: &H256) -> Result<Option<LeafNode<V>>, Error> {
+        let leaf = self.get_leaf(leaf_hash)?;
+        self.remove_leaf(leaf_hash)?;
+        Ok(leaf)
+    }
 }
@@ -1,15 +1,27 @@
 use crate::{
-    collections::{BTreeMap, VecDeque},
+    collections::{BTreeMap, BTreeSet, VecDeque},
     error::{Error, Result},
-    merge::{hash_leaf, merge},
-    merkle_proof::MerkleProof,
+    merge::{hash_leaf, merge_branch},
+    merkle_proof::{AbsenceProof, CircuitProof, MerkleProof, PartialMerkleProof},
+    snapshot::SmtSnapshot,
     traits::{Hasher, Store, Value},
+    vec,
     vec::Vec,
     EXPECTED_PATH_SIZE, H256,
 };
-use core::{cmp::max, marker::PhantomData};
+use core::{
+    cmp::{max, min, Ordering},
+    marker::PhantomData,
+};
 
 /// A branch in the SMT
+///
+/// the store layout is deterministic, it only depends on the leaves not the insertion order:
+/// a leaf is stored as Single(leaf_hash) at its leaf hash with the leaf key and fork_height 0,
+/// a branch is stored as Pair(left, right) at merge_branch(fork_height, key, left, right),
+/// the key is the parent path of the branch, see merge::branch_node_id
+///
+/// Pair(node, sibling) of other keys is still readable, node is on the side of key
 #[derive(Debug, Eq, PartialEq, Clone)]
 pub struct BranchNode {
     pub fork_height: u8,
@@ -51,11 +63,82 @@ pub struct LeafNode<V> {
     pub value: V,
 }
 
+/// Operational metrics of the tree, see `SparseMerkleTree::stats`
+#[derive(Debug, Clone, PartialEq)]
+pub struct TreeStats {
+    /// number of leaves, tombstoned leaves are counted until `compact`
+    pub leaves_count: usize,
+    /// number of branch nodes, include the branch of each leaf
+    pub nodes_count: usize,
+    /// max number of forks from the root to a leaf
+    pub max_depth: usize,
+    /// average number of forks from the root to a leaf
+    pub average_depth: f64,
+    /// estimated bytes of the stored nodes and leaves
+    pub estimated_memory: usize,
+}
+
+/// Leaves to update for syncing a tree to another, see `SparseMerkleTree::diff_delta`
+///
+/// updates are in ascending order of keys, a zero value deletes the key
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TreeDelta {
+    pub updates: Vec<(H256, H256)>,
+}
+
+impl TreeDelta {
+    /// Serialize delta into bytes
+    ///
+    /// layout: updates count u32, then key 32 bytes and value 32 bytes for each update,
+    /// integers are little endian
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(4 + self.updates.len() * 64);
+        buf.extend_from_slice(&(self.updates.len() as u32).to_le_bytes());
+        for (key, value) in &self.updates {
+            buf.extend_from_slice(key.as_slice());
+            buf.extend_from_slice(value.as_slice());
+        }
+        buf
+    }
+
+    /// Deserialize delta from bytes, see serialize for the layout
+    /// return InvalidDeltaLength error if the length does not match the updates count
+    pub fn deserialize(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < 4 {
+            return Err(Error::InvalidDeltaLength(bytes.len()));
+        }
+        let mut count = [0u8; 4];
+        count.copy_from_slice(&bytes[..4]);
+        let count = u32::from_le_bytes(count) as usize;
+        if count.checked_mul(64) != Some(bytes.len() - 4) {
+            return Err(Error::InvalidDeltaLength(bytes.len()));
+        }
+        let updates = bytes[4..]
+            .chunks_exact(64)
+            .map(|chunk| {
+                let mut key = [0u8; 32];
+                let mut value = [0u8; 32];
+                key.copy_from_slice(&chunk[..32]);
+                value.copy_from_slice(&chunk[32..]);
+                (key.into(), value.into())
+            })
+            .collect();
+        Ok(TreeDelta { updates })
+    }
+}
+
 /// Sparse merkle tree
+///
+/// no interior mutability is used, the tree is Send + Sync when H, V and S are
 #[derive(Default, Debug)]
 pub struct SparseMerkleTree<H, V, S> {
     store: S,
     root: H256,
+    lazy_delete: bool,
+    max_proof_keys_factor: Option<usize>,
+    // number of leaves, None if it is unknown, it is counted by set_max_proof_keys
+    leaves_count: Option<usize>,
+    tombstones: BTreeSet<H256>,
     phantom: PhantomData<(H, V)>,
 }
 
@@ -65,6 +148,10 @@ impl<H: Hasher + Default, V: Value, S: Store<V>> SparseMerkleTree<H, V, S> {
         SparseMerkleTree {
             root,
             store,
+            lazy_delete: false,
+            max_proof_keys_factor: None,
+            leaves_count: None,
+            tombstones: Default::default(),
             phantom: PhantomData,
         }
     }
@@ -84,6 +171,23 @@ impl<H: Hasher + Default, V: Value, S: Store<V>> SparseMerkleTree<H, V, S> {
         self.store
     }
 
+    /// Take a read-only snapshot, the store is cloned once and shared by clones of the snapshot
+    /// cloning the store is O(n) unless it is an `Arc<S>`, see SmtSnapshot
+    pub fn snapshot(&self) -> SmtSnapshot<H, V, S>
+    where
+        S: Clone,
+    {
+        SmtSnapshot::new(SparseMerkleTree {
+            store: self.store.clone(),
+            root: self.root,
+            lazy_delete: self.lazy_delete,
+            max_proof_keys_factor: self.max_proof_keys_factor,
+            leaves_count: self.leaves_count,
+            tombstones: self.tombstones.clone(),
+            phantom: PhantomData,
+        })
+    }
+
     /// Get backend store
     pub fn store(&self) -> &S {
         &self.store
@@ -91,21 +195,57 @@ impl<H: Hasher + Default, V: Value, S: Store<V>> SparseMerkleTree<H, V, S> {
 
     /// Get mutable backend store
     pub fn store_mut(&mut self) -> &mut S {
+        // the leaves may be changed through the store
+        self.leaves_count = None;
         &mut self.store
     }
 
     /// Update a leaf, return new merkle root
     /// set to zero value to delete a key
     pub fn update(&mut self, key: H256, value: V) -> Result<&H256> {
+        self.update_inner(key, |_store, _old_leaf| Ok(value))
+    }
+
+    /// Read-modify-write a leaf in one traversal, return new merkle root
+    /// f receives the current value, zero if the key is absent,
+    /// the key is deleted if f returns zero
+    pub fn update_with(&mut self, key: H256, f: impl FnOnce(V) -> V) -> Result<&H256> {
+        // a tombstoned leaf is still stored, but it is removed
+        let tombstoned = self.tombstones.contains(&key);
+        self.update_inner(key, |store, old_leaf| {
+            let current = match old_leaf {
+                Some(_leaf_hash) if tombstoned => V::zero(),
+                Some(leaf_hash) => {
+                    store
+                        .get_leaf(leaf_hash)?
+                        .ok_or(Error::MissingLeaf(*leaf_hash))?
+                        .value
+                }
+                None => V::zero(),
+            };
+            Ok(f(current))
+        })
+    }
+
+    // f computes the new value from the hash of the replaced leaf, if any
+    fn update_inner(
+        &mut self,
+        key: H256,
+        f: impl FnOnce(&S, Option<&H256>) -> Result<V>,
+    ) -> Result<&H256> {
+        trace_span!("update", key = ?key);
         // store the path, sparse index will ignore zero members
         let mut path = Vec::new();
+        // branches are removed after the new value is validated
+        let mut stale_branches = Vec::new();
+        let mut old_leaf = None;
         if !self.is_empty() {
             let mut node = self.root;
             loop {
                 let branch_node = self
                     .store
                     .get_branch(&node)?
-                    .ok_or_else(|| Error::MissingBranch(node))?;
+                    .ok_or(Error::MissingBranch(node))?;
                 let height = max(key.fork_height(branch_node.key()), branch_node.fork_height);
                 match branch_node.node_at(height) {
                     NodeType::Pair(left, right) => {
@@ -114,7 +254,7 @@ impl<H: Hasher + Default, V: Value, S: Store<V>> SparseMerkleTree<H, V, S> {
                             path.push((height, node));
                             break;
                         } else {
-                            self.store.remove_branch(&node)?;
+                            stale_branches.push(node);
                             let is_right = key.get_bit(height);
                             if is_right {
                                 node = right;
@@ -127,8 +267,8 @@ impl<H: Hasher + Default, V: Value, S: Store<V>> SparseMerkleTree<H, V, S> {
                     }
                     NodeType::Single(node) => {
                         if &key == branch_node.key() {
-                            self.store.remove_leaf(&node)?;
-                            self.store.remove_branch(&node)?;
+                            stale_branches.push(node);
+                            old_leaf = Some(node);
                         } else {
                             path.push((height, node));
                         }
@@ -138,8 +278,24 @@ impl<H: Hasher + Default, V: Value, S: Store<V>> SparseMerkleTree<H, V, S> {
             }
         }
 
+        let value = f(&self.store, old_leaf.as_ref())?;
+        if !value.validate() {
+            return Err(Error::InvalidValue(key));
+        }
+        // a new value overrides the pending removal
+        self.tombstones.remove(&key);
+        for node in stale_branches {
+            self.store.remove_branch(&node)?;
+        }
+        if let Some(old_leaf) = old_leaf {
+            self.store.remove_leaf(&old_leaf)?;
+        }
+
         // compute and store new leaf
         let mut node = hash_leaf::<H>(&key, &value.to_h256());
+        if let Some(count) = self.leaves_count.as_mut() {
+            *count = *count + usize::from(!node.is_zero()) - usize::from(old_leaf.is_some());
+        }
         // notice when value is zero the leaf is deleted, so we do not need to store it
         if !node.is_zero() {
             self.store.insert_leaf(node, LeafNode { key, value })?;
@@ -158,18 +314,25 @@ impl<H: Hasher + Default, V: Value, S: Store<V>> SparseMerkleTree<H, V, S> {
         // recompute the tree from bottom to top
         for (height, sibling) in path.into_iter().rev() {
             let is_right = key.get_bit(height);
+            let parent_key = key.parent_path(height);
             let parent = if is_right {
-                merge::<H>(&sibling, &node)
+                merge_branch::<H>(height, &parent_key, &sibling, &node)
             } else {
-                merge::<H>(&node, &sibling)
+                merge_branch::<H>(height, &parent_key, &node, &sibling)
             };
 
             if !node.is_zero() {
                 // node is exists
+                // canonical layout, the key is the parent path and children are (left, right)
+                let node_type = if is_right {
+                    NodeType::Pair(sibling, node)
+                } else {
+                    NodeType::Pair(node, sibling)
+                };
                 let branch_node = BranchNode {
-                    key,
+                    key: parent_key,
                     fork_height: height,
-                    node_type: NodeType::Pair(node, sibling),
+                    node_type,
                 };
                 self.store.insert_branch(parent, branch_node)?;
             }
@@ -179,10 +342,221 @@ impl<H: Hasher + Default, V: Value, S: Store<V>> SparseMerkleTree<H, V, S> {
         Ok(&self.root)
     }
 
+    /// Update a leaf, return true if the root is changed
+    /// the nodes are not rewritten if the value is not changed
+    pub fn update_changed(&mut self, key: H256, value: V) -> Result<bool> {
+        if self.get(&key)?.to_h256() == value.to_h256() {
+            return Ok(false);
+        }
+        let old_root = self.root;
+        Ok(self.update(key, value)? != &old_root)
+    }
+
+    /// Update multiple leaves, return new merkle root
+    /// return DuplicateKey error if a key appears more than once, no leaf is updated in this case
+    pub fn update_all(&mut self, leaves: Vec<(H256, V)>) -> Result<&H256> {
+        let mut keys: Vec<H256> = leaves.iter().map(|(k, _v)| *k).collect();
+        keys.sort_unstable();
+        if let Some(pair) = keys.windows(2).find(|pair| pair[0] == pair[1]) {
+            return Err(Error::DuplicateKey(pair[0]));
+        }
+        self.update_all_last_wins(leaves)
+    }
+
+    /// Update multiple leaves, return new merkle root
+    /// the last value wins if a key appears more than once
+    /// return InvalidValue error if a value is invalid, no leaf is updated in this case
+    pub fn update_all_last_wins(&mut self, leaves: Vec<(H256, V)>) -> Result<&H256> {
+        if let Some((key, _value)) = leaves.iter().find(|(_key, value)| !value.validate()) {
+            return Err(Error::InvalidValue(*key));
+        }
+        for (key, value) in leaves {
+            self.update(key, value)?;
+        }
+        Ok(&self.root)
+    }
+
+    /// Insert all leaves of other tree, return new merkle root
+    /// return DuplicateKey error if a key exists in both trees, no leaf is updated in this case
+    pub fn merge_tree<S2: Store<V>>(&mut self, other: SparseMerkleTree<H, V, S2>) -> Result<&H256> {
+        let leaves = other.leaves().collect::<Result<Vec<_>>>()?;
+        for (key, _value) in &leaves {
+            if !self.get(key)?.to_h256().is_zero() {
+                return Err(Error::DuplicateKey(*key));
+            }
+        }
+        self.update_all(leaves)
+    }
+
+    /// Insert all leaves of other tree, return new merkle root
+    /// resolve(key, value, other_value) returns the value of a key exists in both trees
+    pub fn merge_tree_with<S2: Store<V>, F: FnMut(&H256, V, V) -> V>(
+        &mut self,
+        other: SparseMerkleTree<H, V, S2>,
+        mut resolve: F,
+    ) -> Result<&H256> {
+        let mut leaves = other.leaves().collect::<Result<Vec<_>>>()?;
+        for (key, value) in leaves.iter_mut() {
+            let self_value = self.get(key)?;
+            if !self_value.to_h256().is_zero() {
+                let other_value = core::mem::replace(value, V::zero());
+                *value = resolve(key, self_value, other_value);
+            }
+        }
+        self.update_all(leaves)
+    }
+
+    /// Apply a batch of updates, return new merkle root and a proof of each updated key
+    /// the proofs are generated against the new root,
+    /// return DuplicateKey error if a key appears more than once, no leaf is updated in this case
+    pub fn apply_batch_with_proofs(
+        &mut self,
+        leaves: Vec<(H256, V)>,
+    ) -> Result<(H256, Vec<(H256, MerkleProof)>)> {
+        let keys: Vec<H256> = leaves.iter().map(|(k, _v)| *k).collect();
+        let root = *self.update_all(leaves)?;
+        let proofs = keys
+            .into_iter()
+            .map(|key| Ok((key, self.merkle_proof(vec![key])?)))
+            .collect::<Result<_>>()?;
+        Ok((root, proofs))
+    }
+
+    /// Replace value of an existing leaf, return the old value
+    /// return NonExistentKey error if the key is not in the tree
+    pub fn replace(&mut self, key: H256, value: V) -> Result<V> {
+        let old_value = self.get(&key)?;
+        if old_value.to_h256().is_zero() {
+            return Err(Error::NonExistentKey(key));
+        }
+        self.update(key, value)?;
+        Ok(old_value)
+    }
+
+    /// Enable or disable lazy delete mode
+    /// in lazy delete mode `remove` only marks the key as tombstoned,
+    /// the branches are recomputed once the `compact` is called
+    pub fn set_lazy_delete(&mut self, enabled: bool) {
+        self.lazy_delete = enabled;
+    }
+
+    /// Limit the number of keys of `merkle_proof` to the number of leaves times factor,
+    /// None for unlimited which is the default
+    /// a longer key list is rejected with TooManyKeys error, to catch mistakes early,
+    /// at least factor keys are allowed so absent keys of an empty tree can be proven
+    ///
+    /// the leaves are counted once here, then the count is kept by updates
+    pub fn set_max_proof_keys(&mut self, factor: Option<usize>) -> Result<()> {
+        if factor.is_some() && self.leaves_count.is_none() {
+            self.leaves_count = Some(self.stats()?.leaves_count);
+        }
+        self.max_proof_keys_factor = factor;
+        Ok(())
+    }
+
+    /// Remove a leaf, return merkle root
+    /// in lazy delete mode the root and merkle proofs are not changed until `compact`,
+    /// but `get` returns zero value for tombstoned keys,
+    /// APIs pairing values with proofs return PendingTombstones error until `compact`
+    pub fn remove(&mut self, key: H256) -> Result<&H256> {
+        if self.lazy_delete {
+            self.tombstones.insert(key);
+            Ok(&self.root)
+        } else {
+            self.update(key, V::zero())
+        }
+    }
+
+    /// Remove all tombstoned leaves, return new merkle root
+    /// each affected subtree is rebuilt only once
+    pub fn compact(&mut self) -> Result<&H256> {
+        let keys: Vec<H256> = core::mem::take(&mut self.tombstones).into_iter().collect();
+        if !keys.is_empty() && !self.is_empty() {
+            self.root = self.remove_keys(self.root, &keys)?;
+        }
+        Ok(&self.root)
+    }
+
+    // the leaves of tombstoned keys are still in the root until compact,
+    // so values read by get don't match proofs of the root
+    fn check_no_tombstones(&self) -> Result<()> {
+        if self.tombstones.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::PendingTombstones)
+        }
+    }
+
+    /// Remove sorted keys from the subtree of node, return new hash of the subtree
+    fn remove_keys(&mut self, node: H256, keys: &[H256]) -> Result<H256> {
+        let branch_node = self
+            .store
+            .get_branch(&node)?
+            .ok_or(Error::MissingBranch(node))?;
+        let height = branch_node.fork_height;
+        match branch_node.node_at(height) {
+            NodeType::Pair(left, right) => {
+                // keys of the subtree are contiguous, and keys of left come first
+                let path = branch_node.key().parent_path(height);
+                let mut right_path = path;
+                right_path.set_bit(height);
+                let start = keys.partition_point(|k| k < &path);
+                let end = keys.partition_point(|k| k < &path || k.parent_path(height) == path);
+                let mid = keys.partition_point(|k| k < &right_path);
+                if start == end {
+                    return Ok(node);
+                }
+
+                let new_left = if start < mid {
+                    self.remove_keys(left, &keys[start..mid])?
+                } else {
+                    left
+                };
+                let new_right = if mid < end {
+                    self.remove_keys(right, &keys[mid..end])?
+                } else {
+                    right
+                };
+                if new_left == left && new_right == right {
+                    return Ok(node);
+                }
+
+                self.store.remove_branch(&node)?;
+                if new_left.is_zero() {
+                    return Ok(new_right);
+                } else if new_right.is_zero() {
+                    return Ok(new_left);
+                }
+                let parent = merge_branch::<H>(height, &path, &new_left, &new_right);
+                self.store.insert_branch(
+                    parent,
+                    BranchNode {
+                        key: path,
+                        fork_height: height,
+                        node_type: NodeType::Pair(new_left, new_right),
+                    },
+                )?;
+                Ok(parent)
+            }
+            NodeType::Single(leaf) => {
+                if keys.binary_search(branch_node.key()).is_ok() {
+                    self.store.remove_leaf(&leaf)?;
+                    self.store.remove_branch(&leaf)?;
+                    if let Some(count) = self.leaves_count.as_mut() {
+                        *count -= 1;
+                    }
+                    Ok(H256::zero())
+                } else {
+                    Ok(node)
+                }
+            }
+        }
+    }
+
     /// Get value of a leaf
     /// return zero value if leaf not exists
     pub fn get(&self, key: &H256) -> Result<V> {
-        if self.is_empty() {
+        if self.is_empty() || self.tombstones.contains(key) {
             return Ok(V::zero());
         }
 
@@ -191,10 +565,14 @@ impl<H: Hasher + Default, V: Value, S: Store<V>> SparseMerkleTree<H, V, S> {
             let branch_node = self
                 .store
                 .get_branch(&node)?
-                .ok_or_else(|| Error::MissingBranch(node))?;
+                .ok_or(Error::MissingBranch(node))?;
 
             match branch_node.node_at(branch_node.fork_height) {
                 NodeType::Pair(left, right) => {
+                    // stop if key is out of the subtree
+                    if key.fork_height(branch_node.key()) > branch_node.fork_height {
+                        return Ok(V::zero());
+                    }
                     let is_right = key.get_bit(branch_node.fork_height);
                     node = if is_right { right } else { left };
                 }
@@ -203,7 +581,7 @@ impl<H: Hasher + Default, V: Value, S: Store<V>> SparseMerkleTree<H, V, S> {
                         return Ok(self
                             .store
                             .get_leaf(&node)?
-                            .ok_or_else(|| Error::MissingLeaf(node))?
+                            .ok_or(Error::MissingLeaf(node))?
                             .value);
                     } else {
                         return Ok(V::zero());
@@ -213,6 +591,240 @@ impl<H: Hasher + Default, V: Value, S: Store<V>> SparseMerkleTree<H, V, S> {
         }
     }
 
+    /// Get a reference of the value of a leaf without cloning it
+    /// the reference borrows the tree, so the tree can't be updated while the reference is held
+    ///
+    /// return None if leaf not exists, since a zero value can't be borrowed from the store,
+    /// return RefUnsupported error if the store doesn't lend leaves, see Store::leaf_ref
+    pub fn get_ref(&self, key: &H256) -> Result<Option<&V>> {
+        if self.is_empty() || self.tombstones.contains(key) {
+            return Ok(None);
+        }
+
+        let mut node = self.root;
+        loop {
+            let branch_node = self
+                .store
+                .get_branch(&node)?
+                .ok_or(Error::MissingBranch(node))?;
+
+            match branch_node.node_at(branch_node.fork_height) {
+                NodeType::Pair(left, right) => {
+                    if key.fork_height(branch_node.key()) > branch_node.fork_height {
+                        return Ok(None);
+                    }
+                    let is_right = key.get_bit(branch_node.fork_height);
+                    node = if is_right { right } else { left };
+                }
+                NodeType::Single(node) => {
+                    if key == branch_node.key() {
+                        let leaf = self
+                            .store
+                            .leaf_ref(&node)?
+                            .ok_or(Error::MissingLeaf(node))?;
+                        return Ok(Some(&leaf.value));
+                    } else {
+                        return Ok(None);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Check the stored leaves reachable from the root match their leaf hashes
+    /// return CorruptedLeaf error if the hash of a leaf's key and value differs from where it is stored
+    pub fn verify_leaf_hashes(&self) -> Result<()> {
+        let mut stack = Vec::with_capacity(EXPECTED_PATH_SIZE);
+        if !self.is_empty() {
+            stack.push(self.root);
+        }
+        while let Some(node) = stack.pop() {
+            let branch_node = self
+                .store
+                .get_branch(&node)?
+                .ok_or(Error::MissingBranch(node))?;
+            match branch_node.node_type {
+                NodeType::Pair(node, sibling) => {
+                    stack.push(node);
+                    stack.push(sibling);
+                }
+                NodeType::Single(leaf_hash) => {
+                    let leaf = self
+                        .store
+                        .get_leaf(&leaf_hash)?
+                        .ok_or(Error::MissingLeaf(leaf_hash))?;
+                    if leaf.key != branch_node.key
+                        || hash_leaf::<H>(&leaf.key, &leaf.value.to_h256()) != leaf_hash
+                    {
+                        return Err(Error::CorruptedLeaf {
+                            key: branch_node.key,
+                        });
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Collect operational metrics in one traversal of the tree
+    pub fn stats(&self) -> Result<TreeStats> {
+        let mut leaves_count = 0;
+        let mut nodes_count = 0;
+        let mut max_depth = 0;
+        let mut total_depth = 0;
+        let mut stack = Vec::with_capacity(EXPECTED_PATH_SIZE);
+        if !self.is_empty() {
+            stack.push((self.root, 0));
+        }
+        while let Some((node, depth)) = stack.pop() {
+            let branch_node = self
+                .store
+                .get_branch(&node)?
+                .ok_or(Error::MissingBranch(node))?;
+            nodes_count += 1;
+            match branch_node.node_type {
+                NodeType::Pair(node, sibling) => {
+                    stack.push((node, depth + 1));
+                    stack.push((sibling, depth + 1));
+                }
+                NodeType::Single(_node) => {
+                    leaves_count += 1;
+                    max_depth = max(max_depth, depth);
+                    total_depth += depth;
+                }
+            }
+        }
+        let average_depth = if leaves_count == 0 {
+            0.0
+        } else {
+            total_depth as f64 / leaves_count as f64
+        };
+        let estimated_memory = nodes_count
+            * (core::mem::size_of::<H256>() + core::mem::size_of::<BranchNode>())
+            + leaves_count * (core::mem::size_of::<H256>() + core::mem::size_of::<LeafNode<V>>());
+        Ok(TreeStats {
+            leaves_count,
+            nodes_count,
+            max_depth,
+            average_depth,
+            estimated_memory,
+        })
+    }
+
+    /// Check all keys are present(non-zero value), stop at the first absent key
+    pub fn contains_all(&self, keys: &[H256]) -> Result<bool> {
+        for key in keys {
+            if self.get(key)?.to_h256().is_zero() {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Check any of keys is present(non-zero value), stop at the first present key
+    pub fn contains_any(&self, keys: &[H256]) -> Result<bool> {
+        for key in keys {
+            if !self.get(key)?.to_h256().is_zero() {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Iterate all leaves in order of keys
+    pub fn leaves(&self) -> LeavesIter<'_, V, S> {
+        LeavesIter::new(self, None)
+    }
+
+    /// Iterate all keys in order of keys
+    pub fn keys(&self) -> impl Iterator<Item = Result<H256>> + '_ {
+        self.leaves().map(|leaf| leaf.map(|(key, _value)| key))
+    }
+
+    /// Iterate all values in order of keys
+    pub fn values(&self) -> impl Iterator<Item = Result<V>> + '_ {
+        self.leaves().map(|leaf| leaf.map(|(_key, value)| value))
+    }
+
+    /// Iterate leaves in order of keys,
+    /// the top `prefix_len` bits(from heigher to lower: 255..) of keys are equal to prefix's
+    pub fn prefix_iter(&self, prefix: H256, prefix_len: u8) -> LeavesIter<'_, V, S> {
+        if prefix_len == 0 {
+            return self.leaves();
+        }
+        let start = prefix.copy_bits((256 - prefix_len as usize) as u8);
+        let mut end = start;
+        for i in 0..(256 - prefix_len as usize) as u8 {
+            end.set_bit(i);
+        }
+        LeavesIter::new(self, Some((start, end)))
+    }
+
+    /// Get leaves in range lo <= key < hi in order of keys
+    /// subtrees outside the range are not visited
+    pub fn get_range(&self, lo: H256, hi: H256) -> Result<Vec<(H256, V)>> {
+        match hi.checked_prev() {
+            Some(end) if lo <= end => LeavesIter::new(self, Some((lo, end))).collect(),
+            _ => Ok(Vec::new()),
+        }
+    }
+
+    /// Build a witness tree which only contains the merkle paths of keys,
+    /// other subtrees are summarized by their hashes
+    ///
+    /// the witness has same root, and produces same merkle proofs of these keys
+    pub fn witness_for(&self, keys: &[H256]) -> Result<Self>
+    where
+        S: Default,
+    {
+        let mut store = S::default();
+        if !self.is_empty() {
+            for key in keys {
+                let mut node = self.root;
+                loop {
+                    let branch_node = self
+                        .store
+                        .get_branch(&node)?
+                        .ok_or(Error::MissingBranch(node))?;
+                    store.insert_branch(node, branch_node.clone())?;
+                    let height = max(key.fork_height(branch_node.key()), branch_node.fork_height);
+                    match branch_node.node_at(height) {
+                        NodeType::Pair(left, right) => {
+                            if height > branch_node.fork_height {
+                                break;
+                            }
+                            node = if key.get_bit(height) { right } else { left };
+                        }
+                        NodeType::Single(leaf_hash) => {
+                            if key == branch_node.key() {
+                                let leaf = self
+                                    .store
+                                    .get_leaf(&leaf_hash)?
+                                    .ok_or(Error::MissingLeaf(leaf_hash))?;
+                                store.insert_leaf(leaf_hash, leaf)?;
+                            }
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+        let tombstones = keys
+            .iter()
+            .filter(|key| self.tombstones.contains(key))
+            .cloned()
+            .collect();
+        Ok(SparseMerkleTree {
+            store,
+            root: self.root,
+            lazy_delete: self.lazy_delete,
+            max_proof_keys_factor: self.max_proof_keys_factor,
+            leaves_count: None,
+            tombstones,
+            phantom: PhantomData,
+        })
+    }
+
     /// fetch merkle path of key into cache
     /// cache: (height, key) -> node
     fn fetch_merkle_path(&self, key: &H256, cache: &mut BTreeMap<(u8, H256), H256>) -> Result<()> {
@@ -221,7 +833,7 @@ impl<H: Hasher + Default, V: Value, S: Store<V>> SparseMerkleTree<H, V, S> {
             let branch_node = self
                 .store
                 .get_branch(&node)?
-                .ok_or_else(|| Error::MissingBranch(node))?;
+                .ok_or(Error::MissingBranch(node))?;
             let height = max(key.fork_height(branch_node.key()), branch_node.fork_height);
             let is_right = key.get_bit(height);
             let mut sibling_key = key.parent_path(height);
@@ -265,12 +877,243 @@ impl<H: Hasher + Default, V: Value, S: Store<V>> SparseMerkleTree<H, V, S> {
         Ok(())
     }
 
+    /// Generate merkle proof and the (key, value) pairs it authenticates, absent keys have zero values
+    /// the proof and values are read from same tree state, and can be passed to `MerkleProof::verify`
+    /// return PendingTombstones error in lazy delete mode before `compact`
+    pub fn merkle_proof_with_values(
+        &self,
+        mut keys: Vec<H256>,
+    ) -> Result<(MerkleProof, Vec<(H256, H256)>)> {
+        self.check_no_tombstones()?;
+        keys.sort_unstable();
+        keys.dedup();
+        let leaves = keys
+            .iter()
+            .map(|key| Ok((*key, self.get(key)?.to_h256())))
+            .collect::<Result<_>>()?;
+        let proof = self.merkle_proof(keys)?;
+        Ok((proof, leaves))
+    }
+
+    /// Generate merkle proof with the leaf hashes of keys, see PartialMerkleProof
+    /// return PendingTombstones error in lazy delete mode before `compact`
+    pub fn partial_proof(&self, mut keys: Vec<H256>) -> Result<PartialMerkleProof> {
+        self.check_no_tombstones()?;
+        keys.sort_unstable();
+        keys.dedup();
+        let leaf_hashes = keys
+            .iter()
+            .map(|key| Ok(hash_leaf::<H>(key, &self.get(key)?.to_h256())))
+            .collect::<Result<_>>()?;
+        let proof = self.merkle_proof(keys)?;
+        Ok(PartialMerkleProof { proof, leaf_hashes })
+    }
+
+    /// Generate proof of absence of key with its nearest leaves, see AbsenceProof
+    /// return ExistentKey error if key is present,
+    /// return PendingTombstones error in lazy delete mode before `compact`
+    pub fn prove_absent(&self, key: H256) -> Result<AbsenceProof> {
+        self.check_no_tombstones()?;
+        if !self.get(&key)?.to_h256().is_zero() {
+            return Err(Error::ExistentKey(key));
+        }
+        let left = match key.checked_prev() {
+            Some(end) => LeavesIter::new(self, Some((H256::zero(), end))).last_leaf()?,
+            None => None,
+        };
+        let right = match key.checked_next() {
+            Some(start) => LeavesIter::new(self, Some((start, [u8::MAX; 32].into())))
+                .next()
+                .transpose()?,
+            None => None,
+        };
+        let left = left.map(|(k, v)| (k, v.to_h256()));
+        let right = right.map(|(k, v)| (k, v.to_h256()));
+        let keys = left
+            .iter()
+            .map(|(k, _v)| *k)
+            .chain(Some(key))
+            .chain(right.iter().map(|(k, _v)| *k))
+            .collect();
+        let proof = self.merkle_proof(keys)?;
+        Ok(AbsenceProof {
+            key,
+            left,
+            right,
+            proof,
+        })
+    }
+
+    /// Generate proof of key with exactly 256 siblings, zero for empty subtrees, see CircuitProof
+    pub fn circuit_friendly_proof(&self, key: H256) -> Result<CircuitProof> {
+        let mut siblings = [H256::zero(); 256];
+        // a single leaf proof contains the non-zero siblings of key
+        for (sibling, height) in self.merkle_proof(vec![key])?.take().1 {
+            siblings[height as usize] = sibling;
+        }
+        Ok(CircuitProof { key, siblings })
+    }
+
+    /// Generate consistency proof of old keys against current root, verify it with `MerkleProof::verify_consistency`
+    /// return NonExistentKey error if an old key is removed, an append-only log never removes entries,
+    /// return PendingTombstones error in lazy delete mode before `compact`
+    ///
+    /// the tree only keeps the latest version, so the proof of the old root
+    /// must be generated by `merkle_proof` before the tree is updated
+    pub fn consistency_proof(&self, old_keys: Vec<H256>) -> Result<MerkleProof> {
+        self.check_no_tombstones()?;
+        for key in &old_keys {
+            if self.get(key)?.to_h256().is_zero() {
+                return Err(Error::NonExistentKey(*key));
+            }
+        }
+        self.merkle_proof(old_keys)
+    }
+
+    /// Generate merkle proof for absent keys, verify it with `MerkleProof::verify_absence`
+    /// return PendingTombstones error in lazy delete mode before `compact`
+    ///
+    /// a merkle proof only contains non-zero siblings and never carries leaf values,
+    /// so it shares the path of `merkle_proof`, which already skips leaf fetching
+    pub fn absence_proof(&self, keys: Vec<H256>) -> Result<MerkleProof> {
+        self.check_no_tombstones()?;
+        self.merkle_proof(keys)
+    }
+
     /// Generate merkle proof
+    /// the emission order of siblings is deterministic, see MerkleProof
+    /// a proof of absent keys is verified by `MerkleProof::verify_absence`
+    ///
+    /// distinct keys are proven by walking the path of each key once then merging the paths,
+    /// it costs about the same as a single key proof per key, see the merkle proof benches
     pub fn merkle_proof(&self, mut keys: Vec<H256>) -> Result<MerkleProof> {
+        trace_span!("merkle_proof", keys = keys.len());
         if keys.is_empty() {
             return Err(Error::EmptyKeys);
         }
+        if let Some(factor) = self.max_proof_keys_factor {
+            let leaves_count = match self.leaves_count {
+                Some(count) => count,
+                // the store is changed by store_mut
+                None => self.stats()?.leaves_count,
+            };
+            let max = max(leaves_count, 1).saturating_mul(factor);
+            if keys.len() > max {
+                return Err(Error::TooManyKeys {
+                    max,
+                    actual: keys.len(),
+                });
+            }
+        }
+
+        keys.sort_unstable();
+        if keys.windows(2).any(|pair| pair[0] == pair[1]) {
+            // the proof of duplicate keys has a path for each duplicate
+            return self.multi_keys_proof(keys);
+        }
+        self.per_key_proof(&keys)
+    }
+
+    // generate merkle proof of sorted distinct keys by walking the path of each key once,
+    // the proof is the same as the proof of multi_keys_proof
+    pub(crate) fn per_key_proof(&self, keys: &[H256]) -> Result<MerkleProof> {
+        // height where a key merges into the subtree of the previous key, the first key never does
+        let absorbed: Vec<Option<u8>> = (0..keys.len())
+            .map(|i| {
+                i.checked_sub(1)
+                    .map(|prev| keys[i].fork_height(&keys[prev]))
+            })
+            .collect();
+        // heights where a key merges with subtrees of other keys,
+        // the subtree of the previous key is led by the nearest key absorbed at a higher height
+        let mut merges: Vec<Vec<u8>> = Vec::with_capacity(keys.len());
+        merges.resize_with(keys.len(), Default::default);
+        let mut leaders: Vec<usize> = Vec::with_capacity(keys.len());
+        for (i, height) in absorbed.iter().enumerate() {
+            if let Some(height) = *height {
+                while matches!(leaders.last().and_then(|&j| absorbed[j]), Some(h) if h <= height) {
+                    leaders.pop();
+                }
+                let leader = *leaders.last().expect("the first key leads");
+                merges[leader].push(height);
+                merges[i].push(height);
+            }
+            leaders.push(i);
+        }
 
+        // (node, height, key_index)
+        let mut proof: Vec<(H256, u8, usize)> = Vec::with_capacity(EXPECTED_PATH_SIZE * keys.len());
+        let mut leaves_path: Vec<Vec<u8>> = Vec::with_capacity(keys.len());
+        for (i, key) in keys.iter().enumerate() {
+            let mut path = core::mem::take(&mut merges[i]);
+            let merges_len = path.len();
+            for (sibling, height) in self.key_siblings(key)? {
+                if absorbed[i].is_some_and(|h| height >= h) {
+                    // the higher siblings are proven by the leading key
+                    break;
+                }
+                if !path[..merges_len].contains(&height) {
+                    path.push(height);
+                    proof.push((sibling, height, i));
+                }
+            }
+            path.sort_unstable();
+            if path.is_empty() {
+                // the key merges with no sibling
+                path.push(u8::MAX);
+            }
+            leaves_path.push(path);
+        }
+        // same emission order as multi_keys_proof: from bottom to top, by keys at the same height
+        proof.sort_unstable_by_key(|(_sibling, height, i)| (*height, *i));
+        let proof = proof
+            .into_iter()
+            .map(|(sibling, height, _i)| (sibling, height))
+            .collect::<Vec<_>>();
+        trace_event!(leaves = leaves_path.len(), proof_size = proof.len());
+        Ok(MerkleProof::new(leaves_path, proof))
+    }
+
+    // non-zero siblings of the path of key, (sibling, height) from lower heights to higher heights
+    fn key_siblings(&self, key: &H256) -> Result<Vec<(H256, u8)>> {
+        let mut siblings: Vec<(H256, u8)> = Vec::with_capacity(EXPECTED_PATH_SIZE);
+        if !self.is_empty() {
+            let mut node = self.root;
+            loop {
+                let branch_node = self
+                    .store
+                    .get_branch(&node)?
+                    .ok_or(Error::MissingBranch(node))?;
+                let height = max(key.fork_height(branch_node.key()), branch_node.fork_height);
+                match branch_node.node_at(height) {
+                    NodeType::Pair(left, right) => {
+                        if height > branch_node.fork_height {
+                            // the whole subtree is the sibling
+                            siblings.push((node, height));
+                            break;
+                        } else if key.get_bit(height) {
+                            siblings.push((left, height));
+                            node = right;
+                        } else {
+                            siblings.push((right, height));
+                            node = left;
+                        }
+                    }
+                    NodeType::Single(leaf) => {
+                        if key != branch_node.key() {
+                            siblings.push((leaf, height));
+                        }
+                        break;
+                    }
+                }
+            }
+        }
+        siblings.reverse();
+        Ok(siblings)
+    }
+
+    // generate merkle proof of keys by merging their paths from bottom to top
+    pub(crate) fn multi_keys_proof(&self, mut keys: Vec<H256>) -> Result<MerkleProof> {
         // sort keys
         keys.sort_unstable();
 
@@ -301,7 +1144,7 @@ impl<H: Hasher + Default, V: Value, S: Store<V>> SparseMerkleTree<H, V, S> {
             if queue.is_empty() && cache.is_empty() {
                 // tree only contains one leaf
                 if leaves_path[leaf_index].is_empty() {
-                    leaves_path[leaf_index].push(core::u8::MAX);
+                    leaves_path[leaf_index].push(u8::MAX);
                 }
                 break;
             }
@@ -335,7 +1178,7 @@ impl<H: Hasher + Default, V: Value, S: Store<V>> SparseMerkleTree<H, V, S> {
                         if !is_right {
                             sibling_key.clear_bit(height);
                         }
-                        if height == core::u8::MAX {
+                        if height == u8::MAX {
                             if leaves_path[leaf_index].is_empty() {
                                 leaves_path[leaf_index].push(height);
                             }
@@ -350,7 +1193,7 @@ impl<H: Hasher + Default, V: Value, S: Store<V>> SparseMerkleTree<H, V, S> {
             }
             // find new non-zero sibling, append to leaf's path
             leaves_path[leaf_index].push(height);
-            if height == core::u8::MAX {
+            if height == u8::MAX {
                 break;
             } else {
                 // get parent_key, which k.get_bit(height) is false
@@ -359,6 +1202,407 @@ impl<H: Hasher + Default, V: Value, S: Store<V>> SparseMerkleTree<H, V, S> {
             }
         }
         debug_assert_eq!(leaves_path.len(), keys_len);
+        trace_event!(leaves = leaves_path.len(), proof_size = proof.len());
         Ok(MerkleProof::new(leaves_path, proof))
     }
 }
+
+impl<H: Hasher + Default, S: Store<H256>> SparseMerkleTree<H, H256, S> {
+    /// Compute the delta which moves a tree equal to other to this tree
+    /// leaves are compared in order of keys, only leaves of different values are included
+    /// return PendingTombstones error if either tree is in lazy delete mode before `compact`
+    pub fn diff_delta<S2: Store<H256>>(
+        &self,
+        other: &SparseMerkleTree<H, H256, S2>,
+    ) -> Result<TreeDelta> {
+        self.check_no_tombstones()?;
+        other.check_no_tombstones()?;
+        let mut updates = Vec::new();
+        let mut mine = self.leaves();
+        let mut theirs = other.leaves();
+        let mut next_mine = mine.next().transpose()?;
+        let mut next_theirs = theirs.next().transpose()?;
+        loop {
+            match (next_mine, next_theirs) {
+                (None, None) => break,
+                (Some(leaf), None) => {
+                    updates.push(leaf);
+                    next_mine = mine.next().transpose()?;
+                }
+                (None, Some((key, _value))) => {
+                    updates.push((key, H256::zero()));
+                    next_theirs = theirs.next().transpose()?;
+                }
+                (Some((key, value)), Some((other_key, other_value))) => match key.cmp(&other_key) {
+                    Ordering::Less => {
+                        updates.push((key, value));
+                        next_mine = mine.next().transpose()?;
+                    }
+                    Ordering::Greater => {
+                        updates.push((other_key, H256::zero()));
+                        next_theirs = theirs.next().transpose()?;
+                    }
+                    Ordering::Equal => {
+                        if value != other_value {
+                            updates.push((key, value));
+                        }
+                        next_mine = mine.next().transpose()?;
+                        next_theirs = theirs.next().transpose()?;
+                    }
+                },
+            }
+        }
+        Ok(TreeDelta { updates })
+    }
+
+    /// Apply a delta computed by diff_delta, return new merkle root
+    pub fn apply_delta(&mut self, delta: &TreeDelta) -> Result<&H256> {
+        self.update_all(delta.updates.clone())
+    }
+
+    /// Update multiple leaves, return new merkle root and the watched keys whose proofs are stale
+    /// every other leaf is under a sibling on the path of a key, so the proof of a watched key
+    /// is stale if a leaf of another key is changed, updates writing the current value change nothing
+    /// return DuplicateKey error if a key appears more than once, no leaf is updated in this case
+    pub fn update_all_affecting(
+        &mut self,
+        pairs: Vec<(H256, H256)>,
+        watched_keys: &[H256],
+    ) -> Result<(H256, Vec<H256>)> {
+        let mut keys: Vec<H256> = pairs.iter().map(|(k, _v)| *k).collect();
+        keys.sort_unstable();
+        if let Some(pair) = keys.windows(2).find(|pair| pair[0] == pair[1]) {
+            return Err(Error::DuplicateKey(pair[0]));
+        }
+        let mut changed = Vec::new();
+        for (key, value) in pairs {
+            if self.update_changed(key, value)? {
+                changed.push(key);
+            }
+        }
+        let stale = watched_keys
+            .iter()
+            .filter(|watched| changed.iter().any(|key| key != *watched))
+            .copied()
+            .collect();
+        Ok((self.root, stale))
+    }
+}
+
+#[cfg(feature = "rand")]
+impl<H: Hasher + Default, S: Store<H256> + Default> SparseMerkleTree<H, H256, S> {
+    /// Build a tree of n pseudo-random leaves generated from the seed,
+    /// same seed produces same tree in same version of rand, useful for reproducible benchmarks
+    pub fn deterministic(n: usize, seed: u64) -> Result<Self> {
+        use rand::{rngs::StdRng, Rng, SeedableRng};
+
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut tree = Self::default();
+        for _ in 0..n {
+            let key: [u8; 32] = rng.gen();
+            let value: [u8; 32] = rng.gen();
+            tree.update(key.into(), value.into())?;
+        }
+        Ok(tree)
+    }
+}
+
+impl<'a, H: Hasher + Default, V: Value, S: Store<V>> IntoIterator
+    for &'a SparseMerkleTree<H, V, S>
+{
+    type Item = Result<(H256, V)>;
+    type IntoIter = LeavesIter<'a, V, S>;
+
+    /// Iterate all leaves in order of keys, same as `leaves`
+    fn into_iter(self) -> Self::IntoIter {
+        self.leaves()
+    }
+}
+
+impl<H: Hasher + Default, V: Value, S: Store<V>> IntoIterator for SparseMerkleTree<H, V, S> {
+    type Item = Result<(H256, V)>;
+    type IntoIter = IntoLeavesIter<H, V, S>;
+
+    /// Consume the tree and move leaves out in order of keys
+    fn into_iter(self) -> Self::IntoIter {
+        let mut stack = Vec::with_capacity(EXPECTED_PATH_SIZE);
+        if !self.root.is_zero() {
+            stack.push(self.root);
+        }
+        IntoLeavesIter { tree: self, stack }
+    }
+}
+
+/// Compute merkle root from (key, value) pairs in ascending order of keys,
+/// the result is equal to the root of a tree contains these pairs
+///
+/// nodes are not stored, only O(depth) memory is used to hold unmerged subtrees
+/// return UnsortedKeys error if keys are not ascending
+pub fn root_of_sorted<H: Hasher + Default>(
+    pairs: impl Iterator<Item = (H256, H256)>,
+) -> Result<H256> {
+    // (key, node) of subtrees wait to merge, the fork heights of neighbors decrease to the top
+    let mut stack: Vec<(H256, H256)> = Vec::with_capacity(EXPECTED_PATH_SIZE);
+    let mut last_key: Option<H256> = None;
+    for (key, value) in pairs {
+        if let Some(last_key) = last_key {
+            if key == last_key {
+                return Err(Error::DuplicateKey(key));
+            } else if key < last_key {
+                return Err(Error::UnsortedKeys);
+            }
+        }
+        last_key = Some(key);
+
+        let node = hash_leaf::<H>(&key, &value);
+        // skip zero leaves
+        if node.is_zero() {
+            continue;
+        }
+        if let Some((top_key, _node)) = stack.last() {
+            // subtrees fork lower than the new key will never be merged with a new node
+            let height = top_key.fork_height(&key);
+            merge_sorted_stack::<H>(&mut stack, Some(height));
+        }
+        stack.push((key, node));
+    }
+    merge_sorted_stack::<H>(&mut stack, None);
+    Ok(stack
+        .pop()
+        .map(|(_key, node)| node)
+        .unwrap_or_else(H256::zero))
+}
+
+/// Combine roots of shard trees into the root of a tree contains all shards' leaves
+/// roots are (prefix, prefix_len, root), a shard owns the keys of which top prefix_len bits equal to prefix,
+/// bits of prefix below the prefix are ignored
+///
+/// a subtree with a zero sibling keeps its hash, so a shard root is the node at its prefix,
+/// return NonMergableRange error if shards overlap
+pub fn combine_shard_roots<H: Hasher + Default>(roots: &[(H256, u8, H256)]) -> Result<H256> {
+    let mut shards: Vec<(H256, u8, H256)> = roots
+        .iter()
+        .map(|(prefix, prefix_len, root)| (prefix.mask_prefix(*prefix_len), *prefix_len, *root))
+        .collect();
+    shards.sort_unstable_by_key(|(prefix, _prefix_len, _root)| *prefix);
+    // the prefix is the smallest key of a shard, so overlapped shards are neighbors
+    for pair in shards.windows(2) {
+        let (prefix, prefix_len, _root) = pair[0];
+        let (next_prefix, next_prefix_len, _root) = pair[1];
+        let len = min(prefix_len, next_prefix_len);
+        if prefix.mask_prefix(len) == next_prefix.mask_prefix(len) {
+            return Err(Error::NonMergableRange);
+        }
+    }
+    let mut stack: Vec<(H256, H256)> = Vec::with_capacity(EXPECTED_PATH_SIZE);
+    for (prefix, _prefix_len, root) in shards {
+        // skip empty shards
+        if root.is_zero() {
+            continue;
+        }
+        if let Some((top_key, _node)) = stack.last() {
+            let height = top_key.fork_height(&prefix);
+            merge_sorted_stack::<H>(&mut stack, Some(height));
+        }
+        stack.push((prefix, root));
+    }
+    merge_sorted_stack::<H>(&mut stack, None);
+    Ok(stack
+        .pop()
+        .map(|(_key, node)| node)
+        .unwrap_or_else(H256::zero))
+}
+
+// merge subtrees on top of the stack which fork lower than height, or merge all if height is None
+fn merge_sorted_stack<H: Hasher + Default>(stack: &mut Vec<(H256, H256)>, height: Option<u8>) {
+    while stack.len() >= 2 {
+        let (right_key, right) = stack[stack.len() - 1];
+        let (left_key, left) = stack[stack.len() - 2];
+        let fork_height = left_key.fork_height(&right_key);
+        if let Some(height) = height {
+            if fork_height >= height {
+                break;
+            }
+        }
+        stack.truncate(stack.len() - 2);
+        let parent = merge_branch::<H>(
+            fork_height,
+            &left_key.parent_path(fork_height),
+            &left,
+            &right,
+        );
+        stack.push((left_key, parent));
+    }
+}
+
+/// An iterator over leaves of the tree in order of keys
+pub struct LeavesIter<'a, V, S> {
+    store: &'a S,
+    tombstones: &'a BTreeSet<H256>,
+    // subtrees wait to visit, the left most one on the top
+    stack: Vec<H256>,
+    // only visit keys in the range (start, end), both inclusive
+    range: Option<(H256, H256)>,
+    phantom: PhantomData<V>,
+}
+
+impl<'a, V, S: Store<V>> LeavesIter<'a, V, S> {
+    fn new<H>(tree: &'a SparseMerkleTree<H, V, S>, range: Option<(H256, H256)>) -> Self {
+        let mut stack = Vec::with_capacity(EXPECTED_PATH_SIZE);
+        if !tree.root.is_zero() {
+            stack.push(tree.root);
+        }
+        LeavesIter {
+            store: &tree.store,
+            tombstones: &tree.tombstones,
+            stack,
+            range,
+            phantom: PhantomData,
+        }
+    }
+
+    // check subtree of path at height overlap with the range
+    fn overlap(&self, path: &H256, height: u8) -> bool {
+        match &self.range {
+            // the subtree covers keys from path to path with all lower bits set
+            Some((start, end)) => path <= end && &start.copy_bits(height) <= path,
+            None => true,
+        }
+    }
+
+    fn contains(&self, key: &H256) -> bool {
+        match &self.range {
+            Some((start, end)) => start <= key && key <= end,
+            None => true,
+        }
+    }
+
+    fn next_leaf(&mut self) -> Result<Option<(H256, V)>> {
+        match self.next_leaf_hash()? {
+            Some((key, leaf_hash)) => {
+                let leaf = self
+                    .store
+                    .get_leaf(&leaf_hash)?
+                    .ok_or(Error::MissingLeaf(leaf_hash))?;
+                Ok(Some((key, leaf.value)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    // return key and leaf hash of the next leaf
+    fn next_leaf_hash(&mut self) -> Result<Option<(H256, H256)>> {
+        self.pop_leaf_hash(false)
+    }
+
+    // return the last leaf in the range, subtrees are visited from right to left
+    // so only the subtrees on the path of the last leaf are loaded
+    fn last_leaf(mut self) -> Result<Option<(H256, V)>> {
+        match self.pop_leaf_hash(true)? {
+            Some((key, leaf_hash)) => {
+                let leaf = self
+                    .store
+                    .get_leaf(&leaf_hash)?
+                    .ok_or(Error::MissingLeaf(leaf_hash))?;
+                Ok(Some((key, leaf.value)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    // depth first search the leaves in the range, the left subtree is visited first unless right_first
+    fn pop_leaf_hash(&mut self, right_first: bool) -> Result<Option<(H256, H256)>> {
+        while let Some(node) = self.stack.pop() {
+            let branch_node = self
+                .store
+                .get_branch(&node)?
+                .ok_or(Error::MissingBranch(node))?;
+            let height = branch_node.fork_height;
+            match branch_node.node_at(height) {
+                NodeType::Pair(left, right) => {
+                    let left_path = branch_node.key().parent_path(height);
+                    let mut right_path = left_path;
+                    right_path.set_bit(height);
+                    let (first, first_path, second, second_path) = if right_first {
+                        (right, right_path, left, left_path)
+                    } else {
+                        (left, left_path, right, right_path)
+                    };
+                    if self.overlap(&second_path, height) {
+                        self.stack.push(second);
+                    }
+                    if self.overlap(&first_path, height) {
+                        self.stack.push(first);
+                    }
+                }
+                NodeType::Single(node) => {
+                    let key = branch_node.key;
+                    if !self.contains(&key) || self.tombstones.contains(&key) {
+                        continue;
+                    }
+                    return Ok(Some((key, node)));
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// An iterator moves leaves out of a consumed tree in order of keys,
+/// values are taken from the store by Store::take_leaf
+pub struct IntoLeavesIter<H, V, S> {
+    tree: SparseMerkleTree<H, V, S>,
+    // subtrees wait to visit, the left most one on the top
+    stack: Vec<H256>,
+}
+
+impl<H, V, S: Store<V>> IntoLeavesIter<H, V, S> {
+    fn next_leaf(&mut self) -> Result<Option<(H256, V)>> {
+        let mut iter = LeavesIter {
+            store: &self.tree.store,
+            tombstones: &self.tree.tombstones,
+            stack: core::mem::take(&mut self.stack),
+            range: None,
+            phantom: PhantomData,
+        };
+        let next = iter.next_leaf_hash();
+        self.stack = iter.stack;
+        match next? {
+            Some((key, leaf_hash)) => {
+                let leaf = self
+                    .tree
+                    .store
+                    .take_leaf(&leaf_hash)?
+                    .ok_or(Error::MissingLeaf(leaf_hash))?;
+                Ok(Some((key, leaf.value)))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+impl<H, V, S: Store<V>> Iterator for IntoLeavesIter<H, V, S> {
+    type Item = Result<(H256, V)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let result = self.next_leaf();
+        if result.is_err() {
+            // stop iteration after an error
+            self.stack.clear();
+        }
+        result.transpose()
+    }
+}
+
+impl<'a, V, S: Store<V>> Iterator for LeavesIter<'a, V, S> {
+    type Item = Result<(H256, V)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let result = self.next_leaf();
+        if result.is_err() {
+            // stop iteration after an error
+            self.stack.clear();
+        }
+        result.transpose()
+    }
+}
@@ -0,0 +1,134 @@
+//! Golden vectors of roots and serialized proofs with Blake2bHasher
+//!
+//! the vectors are checked in at fixtures/vectors.json, a change of the hashing or
+//! the proof wire format breaks this test, regenerate the vectors only for an intended change:
+//!
+//! ```text
+//! cargo test --test vectors -- --ignored gen_vectors
+//! ```
+
+use serde::{Deserialize, Serialize};
+use sparse_merkle_tree::{
+    blake2b::Blake2bHasher, default_store::DefaultStore, MerkleProof, SparseMerkleTree, H256,
+};
+use std::fs;
+
+const VECTORS_PATH: &str = "fixtures/vectors.json";
+
+#[allow(clippy::upper_case_acronyms)]
+type SMT = SparseMerkleTree<Blake2bHasher, H256, DefaultStore<H256>>;
+
+/// bytes are hex encoded
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Vector {
+    name: String,
+    keys: Vec<String>,
+    values: Vec<String>,
+    root: String,
+    proof_keys: Vec<String>,
+    proof: String,
+}
+
+fn h256(s: &str) -> H256 {
+    H256::from_hex(s).expect("hex")
+}
+
+// a deterministic key or value
+fn pattern(seed: u8, i: u8) -> H256 {
+    let mut buf = [0u8; 32];
+    for (j, byte) in buf.iter_mut().enumerate() {
+        *byte = seed
+            .wrapping_mul(31)
+            .wrapping_add(i.wrapping_mul(17))
+            .wrapping_add(j as u8)
+            .rotate_left(i as u32 % 8);
+    }
+    buf.into()
+}
+
+fn gen_vector(name: &str, pairs: Vec<(H256, H256)>, proof_keys: Vec<H256>) -> Vector {
+    let mut smt = SMT::default();
+    for (key, value) in &pairs {
+        smt.update(*key, *value).expect("update");
+    }
+    let proof = smt.merkle_proof(proof_keys.clone()).expect("proof");
+    Vector {
+        name: name.to_string(),
+        keys: pairs.iter().map(|(k, _v)| hex::encode(k)).collect(),
+        values: pairs.iter().map(|(_k, v)| hex::encode(v)).collect(),
+        root: hex::encode(smt.root()),
+        proof_keys: proof_keys.iter().map(hex::encode).collect(),
+        proof: hex::encode(proof.serialize::<Blake2bHasher>()),
+    }
+}
+
+fn gen_all_vectors() -> Vec<Vector> {
+    let leaves = |seed: u8, n: u8| -> Vec<(H256, H256)> {
+        (0..n)
+            .map(|i| (pattern(seed, i), pattern(seed + 1, i)))
+            .collect()
+    };
+    let keys = |pairs: &[(H256, H256)], indexes: &[usize]| -> Vec<H256> {
+        indexes.iter().map(|i| pairs[*i].0).collect()
+    };
+    let single = leaves(1, 1);
+    let small = leaves(3, 3);
+    let large = leaves(5, 20);
+    vec![
+        gen_vector("empty", Vec::new(), vec![pattern(0, 0)]),
+        gen_vector("single", single.clone(), keys(&single, &[0])),
+        gen_vector("single absent", single, vec![pattern(0, 0)]),
+        gen_vector("small", small.clone(), keys(&small, &[0, 2])),
+        gen_vector(
+            "small with absent",
+            small.clone(),
+            vec![small[1].0, pattern(0, 1)],
+        ),
+        gen_vector("large", large.clone(), keys(&large, &[1, 3, 5, 7, 11, 19])),
+        gen_vector("large single", large.clone(), keys(&large, &[10])),
+    ]
+}
+
+#[test]
+#[ignore]
+fn gen_vectors() {
+    let content = serde_json::to_string_pretty(&gen_all_vectors()).expect("to json");
+    fs::write(VECTORS_PATH, content + "\n").expect("write");
+}
+
+#[test]
+fn test_vectors() {
+    let content = fs::read(VECTORS_PATH).expect("read");
+    let vectors: Vec<Vector> = serde_json::from_slice(&content).expect("parse json");
+    assert!(vectors.len() >= 5);
+    for vector in vectors {
+        let mut smt = SMT::default();
+        for (key, value) in vector.keys.iter().zip(&vector.values) {
+            smt.update(h256(key), h256(value)).expect("update");
+        }
+        assert_eq!(smt.root(), &h256(&vector.root), "root of {}", vector.name);
+
+        let proof_keys: Vec<H256> = vector.proof_keys.iter().map(|k| h256(k)).collect();
+        let proof = smt.merkle_proof(proof_keys.clone()).expect("proof");
+        let bytes = hex::decode(&vector.proof).expect("hex");
+        assert_eq!(
+            proof.serialize::<Blake2bHasher>(),
+            bytes,
+            "proof of {}",
+            vector.name
+        );
+
+        let leaves = proof_keys
+            .iter()
+            .map(|k| (*k, smt.get(k).expect("get")))
+            .collect();
+        let proof = MerkleProof::deserialize::<Blake2bHasher>(&bytes).expect("deserialize");
+        assert!(
+            proof
+                .verify::<Blake2bHasher>(&h256(&vector.root), leaves)
+                .expect("verify"),
+            "verify {}",
+            vector.name
+        );
+    }
+}